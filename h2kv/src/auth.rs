@@ -0,0 +1,223 @@
+use std::path::Path;
+
+use anyhow::{Result, anyhow, bail};
+use http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+
+/// Bearer tokens which grant access to HTTP methods on the keys matching a set of globs.
+/// Tokens are identified by the SHA-256 digest of their secret, so the secrets are not stored.
+/// Every request is allowed if no tokens are defined.
+#[derive(Debug, Clone, Default)]
+pub struct Tokens {
+    scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Clone)]
+struct Scope {
+    /// Lowercase hex digest of the secret.
+    digest: String,
+    /// Every method is allowed if this is `None`.
+    methods: Option<Vec<Method>>,
+    patterns: Vec<glob::Pattern>,
+}
+
+impl Tokens {
+    pub const FILE_DESCRIPTION: &str = r#"
+    Used with --tokens-file option to require an "Authorization: Bearer" header.
+    Format:
+    Lines of the SHA-256 digest of a secret in hex, followed by the methods it allows
+    separated by commas (or '*' for all of them), and the glob patterns of the keys
+    it allows them on. GET also allows HEAD. Lines with the same digest are combined.
+    Comments allowed between '#' and end of line.
+    Pattern syntax: https://docs.rs/glob/latest/glob/struct.Pattern.html
+    Example: "2bb80d53...7f4f  GET,PUT  /public/** /drafts/*.md"
+    "#;
+
+    pub fn try_from_file(path: &Path) -> Result<Self> {
+        let tokens = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("unreadable tokens file {path:?}: {e}"))?;
+        Self::try_from_str(&tokens)
+    }
+
+    pub fn try_from_str(tokens: &str) -> Result<Self> {
+        let mut scopes = vec![];
+        for line in tokens.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(digest), Some(methods)) = (fields.next(), fields.next()) else {
+                bail!("expected a digest, methods and key globs: '{line}'");
+            };
+            if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("expected a SHA-256 digest in hex: '{digest}'");
+            }
+            let methods = match methods {
+                "*" => None,
+                methods => Some(
+                    methods
+                        .split(',')
+                        .map(|m| Method::from_bytes(m.to_ascii_uppercase().as_bytes()))
+                        .collect::<std::result::Result<Vec<_>, _>>()?,
+                ),
+            };
+            let patterns = fields
+                .map(glob::Pattern::new)
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            if patterns.is_empty() {
+                bail!("no key globs for token {digest}");
+            }
+            scopes.push(Scope {
+                digest: digest.to_ascii_lowercase(),
+                methods,
+                patterns,
+            });
+        }
+        Ok(Self { scopes })
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.scopes.is_empty()
+    }
+
+    /// Check the bearer token of a request for `method` on each of `paths`.
+    /// Returns the status and headers of the response refusing the request, if it is refused.
+    /// https://www.rfc-editor.org/rfc/rfc6750.html#section-3
+    pub fn refusal(
+        &self,
+        headers: &HeaderMap,
+        method: &Method,
+        paths: &[&Path],
+    ) -> Option<(StatusCode, HeaderMap)> {
+        if !self.is_active() {
+            return None;
+        }
+        let Some(token) = bearer_token(headers) else {
+            return Some(challenge(StatusCode::UNAUTHORIZED, None));
+        };
+
+        let digest = sha256_hex(token.as_bytes());
+        let scopes: Vec<&Scope> = self.scopes.iter().filter(|s| s.digest == digest).collect();
+        if scopes.is_empty() {
+            return Some(challenge(StatusCode::UNAUTHORIZED, Some("invalid_token")));
+        }
+        let permitted = |path: &Path| scopes.iter().any(|s| s.permits(method, path));
+        match paths.iter().all(|path| permitted(path)) {
+            true => None,
+            false => Some(challenge(StatusCode::FORBIDDEN, Some("insufficient_scope"))),
+        }
+    }
+}
+
+impl Scope {
+    fn permits(&self, method: &Method, path: &Path) -> bool {
+        let options = glob::MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+        let method_allowed = self.methods.as_ref().is_none_or(|methods| {
+            methods.contains(method) || (method == Method::HEAD && methods.contains(&Method::GET))
+        });
+        method_allowed
+            && self
+                .patterns
+                .iter()
+                .any(|pattern| pattern.matches_path_with(path, options))
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let authorization = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = authorization.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+fn challenge(status: StatusCode, error: Option<&str>) -> (StatusCode, HeaderMap) {
+    let value = match error {
+        None => r#"Bearer realm="h2kv""#.to_string(),
+        Some(error) => format!(r#"Bearer realm="h2kv", error="{error}""#),
+    };
+    let mut headers = HeaderMap::new();
+    headers.append(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_str(&value).unwrap(),
+    );
+    (status, headers)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, data);
+    digest.as_ref().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bearer(token: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(header::AUTHORIZATION, HeaderValue::from_static(token))])
+    }
+
+    #[test]
+    fn test_refusal() {
+        let tokens = Tokens::try_from_str(&format!(
+            "{}  GET,put  /public/** # comment\n{}  *  /**",
+            sha256_hex(b"reader"),
+            sha256_hex(b"admin"),
+        ))
+        .unwrap();
+        let refused = |headers: &HeaderMap, method: Method, path: &str| {
+            tokens
+                .refusal(headers, &method, &[Path::new(path)])
+                .map(|(status, _)| status)
+        };
+
+        assert_eq!(
+            refused(&HeaderMap::new(), Method::GET, "/public/a.txt"),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            refused(&bearer("Bearer unknown"), Method::GET, "/public/a.txt"),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            refused(&bearer("Bearer reader"), Method::HEAD, "/public/docs/a.txt"),
+            None
+        );
+        assert_eq!(
+            refused(&bearer("bearer reader"), Method::PUT, "/public/a.txt"),
+            None
+        );
+        assert_eq!(
+            refused(&bearer("Bearer reader"), Method::DELETE, "/public/a.txt"),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            refused(&bearer("Bearer reader"), Method::GET, "/private.txt"),
+            Some(StatusCode::FORBIDDEN)
+        );
+        assert_eq!(
+            refused(&bearer("Bearer admin"), Method::DELETE, "/private.txt"),
+            None
+        );
+
+        let (_, headers) = tokens
+            .refusal(&HeaderMap::new(), &Method::GET, &[Path::new("/")])
+            .unwrap();
+        assert_eq!(headers[header::WWW_AUTHENTICATE], r#"Bearer realm="h2kv""#);
+        assert!(
+            Tokens::default()
+                .refusal(&HeaderMap::new(), &Method::GET, &[])
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_try_from_str() {
+        assert!(Tokens::try_from_str("# nothing\n").is_ok_and(|t| !t.is_active()));
+        assert!(Tokens::try_from_str("abc GET /**").is_err());
+        assert!(Tokens::try_from_str(&format!("{} GET", sha256_hex(b"x"))).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use http::{HeaderMap, HeaderValue, header};
+
+/// Upper bound for the *wait* preference of a long-poll request.
+pub const MAX_WAIT: Duration = Duration::from_secs(300);
+
+/// Strong entity tag derived from the stored value.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-8.8.3
+pub fn etag(value: &[u8]) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{:x}\"", md5::compute(value))).unwrap()
+}
+
+/// Evaluate the *if-none-match* precondition against the current entity tag.
+/// Returns `true` if the header is present and one of its entity tags matches,
+/// using the weak comparison function.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-13.1.2
+pub fn none_match(headers: &HeaderMap, etag: Option<&HeaderValue>) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(
+            |tag| match (tag.trim(), etag.and_then(|e| e.to_str().ok())) {
+                ("*", current) => current.is_some(),
+                (tag, Some(current)) => opaque(tag) == opaque(current),
+                (_, None) => false,
+            },
+        )
+}
+
+/// The *wait* preference of a request, limited to [`MAX_WAIT`].
+/// https://www.rfc-editor.org/rfc/rfc7240.html#section-4.3
+pub fn wait_preference(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get_all("prefer")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|pref| {
+            let (name, value) = pref.split(';').next()?.split_once('=')?;
+            match name.trim().eq_ignore_ascii_case("wait") {
+                true => value.trim().trim_matches('"').parse::<u64>().ok(),
+                false => None,
+            }
+        })
+        .next()
+        .map(|secs| Duration::from_secs(secs).min(MAX_WAIT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_none_match() {
+        let current = etag(b"foo");
+        let tag = current.to_str().unwrap().to_string();
+
+        let matching = HeaderMap::from_iter([(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_str(&format!("\"other\", W/{tag}")).unwrap(),
+        )]);
+        assert!(none_match(&matching, Some(&current)));
+        assert!(!none_match(&matching, Some(&etag(b"bar"))));
+        assert!(!none_match(&matching, None));
+
+        let any = headers(&[("if-none-match", "*")]);
+        assert!(none_match(&any, Some(&current)));
+        assert!(!none_match(&any, None));
+
+        assert!(!none_match(&HeaderMap::new(), Some(&current)));
+    }
+
+    #[test]
+    fn test_wait_preference() {
+        let prefer = headers(&[("prefer", "respond-async, wait=30")]);
+        assert_eq!(wait_preference(&prefer), Some(Duration::from_secs(30)));
+
+        let prefer = headers(&[("prefer", "wait=100000")]);
+        assert_eq!(wait_preference(&prefer), Some(MAX_WAIT));
+
+        let prefer = headers(&[("prefer", "return=minimal")]);
+        assert_eq!(wait_preference(&prefer), None);
+    }
+}
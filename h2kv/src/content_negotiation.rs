@@ -1,16 +1,19 @@
-use std::cmp::Ordering;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
 use headers_accept::Accept;
-use http::{HeaderMap, HeaderValue, header};
-use mediatype::{MediaType, names::*};
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+use mediatype::{MediaType, MediaTypeBuf, ReadParams, names::*};
 use mime2ext::mime2ext;
 
+use crate::Config;
 use crate::storage::StorageBackend;
+use crate::transcoding::{self, Format, Transcode};
 
 /// The storage key and content-type needed to satisfy an HTTP request.
 /// All generated storage keys have a file extension to indicate the content type.
@@ -20,6 +23,8 @@ use crate::storage::StorageBackend;
 pub struct NegotiatedPath<'a> {
     storage_key: PathBuf,
     media_type: MediaType<'a>,
+    language: Option<String>,
+    transcode: Option<Transcode>,
 }
 
 impl<'a> NegotiatedPath<'a> {
@@ -28,24 +33,21 @@ impl<'a> NegotiatedPath<'a> {
 
     /// If negotiation fails, `Ok(None)` is returned to indicate
     /// that the value of the *content-type* header is not acceptable.
+    /// A single language tag in the *content-language* header is added to the storage key,
+    /// so that translations of a resource are stored side by side, e.g. `/about.fr.html`.
     pub fn for_write(path: &Path, headers: &'a HeaderMap) -> Result<Option<Self>> {
-        match (path.extension(), headers.get(header::CONTENT_TYPE)) {
+        let (storage_key, media_type) = match (path.extension(), headers.get(header::CONTENT_TYPE))
+        {
             // providing only an extension is acceptable, use generic content-type
-            (Some(_path_ext), None) => Ok(Some(Self {
-                storage_key: path.to_owned(),
-                media_type: Self::GENERIC_MEDIA_TYPE,
-            })),
+            (Some(_path_ext), None) => (path.to_owned(), Self::GENERIC_MEDIA_TYPE),
             // guess extension from content-type, if recognized
             (None, Some(content_type)) => {
                 let content_type = content_type.to_str()?;
                 match MediaType::parse(content_type) {
-                    Err(_) => Ok(None),
+                    Err(_) => return Ok(None),
                     Ok(media_type) => {
                         let ext = mime2ext(content_type).unwrap_or(Self::GENERIC_EXT);
-                        Ok(Some(Self {
-                            storage_key: path.with_extension(ext),
-                            media_type,
-                        }))
+                        (path.with_extension(ext), media_type)
                     }
                 }
             }
@@ -53,70 +55,154 @@ impl<'a> NegotiatedPath<'a> {
             (Some(_path_ext), Some(content_type)) => {
                 let content_type = content_type.to_str()?;
                 match MediaType::parse(content_type) {
-                    Err(_) => Ok(None),
-                    Ok(media_type) => Ok(Some(Self {
-                        storage_key: path.to_owned(),
-                        media_type,
-                    })),
+                    Err(_) => return Ok(None),
+                    Ok(media_type) => (path.to_owned(), media_type),
                 }
             }
             // use defaults
-            (None, None) => Ok(Some(Self {
-                storage_key: path.with_extension(Self::GENERIC_EXT),
-                media_type: Self::GENERIC_MEDIA_TYPE,
+            (None, None) => (
+                path.with_extension(Self::GENERIC_EXT),
+                Self::GENERIC_MEDIA_TYPE,
+            ),
+        };
+
+        match content_language(headers) {
+            None => Ok(Some(Self {
+                storage_key,
+                media_type,
+                language: None,
+                transcode: None,
             })),
+            Some(language) => {
+                let ext = storage_key.extension().unwrap().to_string_lossy();
+                Ok(Some(Self {
+                    storage_key: crate::util::path_stem(&storage_key)
+                        .with_extension(format!("{language}.{ext}")),
+                    media_type,
+                    language: Some(language),
+                    transcode: None,
+                }))
+            }
         }
     }
 
     /// Use `extensions` to find a specific representation
-    /// or fall back to matching using the *accept* header.
+    /// or fall back to matching using the *accept* headers and preferences of the server.
+    /// A path with the extension of a type, but not a language, is matched
+    /// against the translations of that type using *accept-language*.
+    /// Where transcoding is enabled, structured data is also available in the other formats.
     /// If negotiation fails, `Ok(None)` is returned to indicate that
     /// no acceptable content was found to serve in response.
     pub fn for_read(
         path: &Path,
         extensions: &'a PathExtensions,
         headers: &HeaderMap,
+        config: &Config,
     ) -> Result<Option<Self>> {
+        let preferences = &config.type_preferences;
+        let canonical = config.transcoding.canonical_for(path);
+        let with_convertible = |mut available: Vec<Representation<'a>>| {
+            if let Some(canonical) = canonical {
+                available.extend(transcoding::convertible(&available, canonical));
+            }
+            available
+        };
+
         match (path.extension(), headers.get(header::ACCEPT)) {
             // search by file extension
             (Some(path_ext), _) if path_ext != Self::GENERIC_EXT => {
-                let path_ext = path_ext.to_str().unwrap();
-                match extensions.get_media_type(path_ext)? {
-                    None => Ok(None),
-                    Some(media_type) => Ok(Some(Self {
+                let ext = extensions.extension_of(path).unwrap();
+                if let Some(media_type) = extensions.get_media_type(&ext)? {
+                    return Ok(Some(Self {
                         storage_key: path.to_owned(),
                         media_type,
-                    })),
+                        language: extensions.get_language(&ext).map(str::to_string),
+                        transcode: None,
+                    }));
+                }
+
+                // a path such as `/about.fr.html` does not match other translations
+                if Path::new(path.file_stem().unwrap()).extension().is_some() {
+                    return Ok(None);
+                }
+                let suffix = format!(".{}", path_ext.to_string_lossy());
+                let translations = extensions
+                    .representations()?
+                    .into_iter()
+                    .filter(|r| r.language.is_some() && r.extension.ends_with(&suffix))
+                    .collect();
+                let acceptable = Acceptable::from_headers(None, headers)?;
+                if let Some(translation) = rank(&acceptable, &[], translations).into_iter().next() {
+                    return Ok(Some(Self::for_representation(
+                        &crate::util::path_stem(path),
+                        translation,
+                    )));
                 }
+
+                // convert from another format
+                let Some(format) = Format::from_name(&path_ext.to_string_lossy()) else {
+                    return Ok(None);
+                };
+                Ok(with_convertible(extensions.representations()?)
+                    .into_iter()
+                    .find(|r| r.transcode.as_ref().is_some_and(|t| t.to == format))
+                    .map(|r| Self {
+                        storage_key: path.to_owned(),
+                        ..Self::for_representation(path, r)
+                    }))
             }
-            // use the "accept" header to match against available types
+            // use the "accept" headers to match against available types
             (None, Some(accept)) => {
-                let accept = accept.to_str()?;
-                let accept = Accept::from_str(accept)?;
-                let available = extensions.get_all_media_types()?;
-                match accept.negotiate(&available) {
-                    None => Ok(None),
-                    Some(media_type) => {
-                        let ext = extensions.get_extension(media_type)?.unwrap();
-                        Ok(Some(Self {
-                            storage_key: path.with_extension(ext),
-                            media_type: media_type.clone(),
-                        }))
-                    }
-                }
+                let acceptable = Acceptable::from_headers(Some(accept), headers)?;
+                let available = with_convertible(extensions.representations()?);
+                Ok(rank(&acceptable, preferences.for_path(path), available)
+                    .into_iter()
+                    .next()
+                    .map(|r| Self::for_representation(path, r)))
             }
             // generic type available
-            (None, None) if extensions.map.contains_key(Self::GENERIC_EXT) => Ok(Some(Self {
-                storage_key: path.with_extension(Self::GENERIC_EXT),
-                media_type: Self::GENERIC_MEDIA_TYPE,
-            })),
+            (None, None)
+                if extensions
+                    .map
+                    .get(Self::GENERIC_EXT)
+                    .is_some_and(|entry| !is_expired(entry)) =>
+            {
+                Ok(Some(Self {
+                    storage_key: path.with_extension(Self::GENERIC_EXT),
+                    media_type: Self::GENERIC_MEDIA_TYPE,
+                    language: None,
+                    transcode: None,
+                }))
+            }
+            // otherwise the only representation, or the one preferred by the server
+            (None, None) => {
+                let available = extensions.representations()?;
+                let preferred = preferences.for_path(path);
+                if available.len() > 1 && preferred.is_empty() {
+                    return Ok(None);
+                }
+                let acceptable = Acceptable::from_headers(None, headers)?;
+                Ok(rank(&acceptable, preferred, with_convertible(available))
+                    .into_iter()
+                    .next()
+                    .map(|r| Self::for_representation(path, r)))
+            }
             // otherwise not found
             _ => Ok(None),
         }
     }
 
+    fn for_representation(path: &Path, representation: Representation<'a>) -> Self {
+        Self {
+            storage_key: path.with_extension(representation.extension),
+            media_type: representation.media_type,
+            language: representation.language.map(str::to_string),
+            transcode: representation.transcode,
+        }
+    }
+
     pub fn guess_media_type(&mut self) -> Result<()> {
-        let ext = self.storage_extension();
+        let ext = self.storage_key.extension().unwrap().to_string_lossy();
         let guess = new_mime_guess::from_ext(&ext)
             .first_raw()
             .ok_or(anyhow!("no known media type for '.{ext}' extension"))?;
@@ -124,16 +210,70 @@ impl<'a> NegotiatedPath<'a> {
         Ok(())
     }
 
+    /// Detect the media type from the first bytes of `content`, for the binary formats
+    /// which have a signature. A generic storage key is given the extension of the media type.
+    pub fn sniff_media_type(&mut self, content: &[u8]) -> Result<()> {
+        let sniffed = infer::get(content).ok_or(anyhow!("no known signature"))?;
+        self.media_type = MediaType::parse(sniffed.mime_type())?;
+        if self.storage_key.extension() == Some(Self::GENERIC_EXT.as_ref()) {
+            self.storage_key.set_extension(sniffed.extension());
+        }
+        Ok(())
+    }
+
+    /// Store a structured data representation in another `format`.
+    pub fn set_format(&mut self, format: Format) {
+        self.media_type = format.media_type();
+        self.storage_key.set_extension(format.name());
+    }
+
+    pub fn media_type(&self) -> &MediaType<'a> {
+        &self.media_type
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    /// The conversion from a stored representation, if the representation is not stored.
+    pub fn transcode(&self) -> Option<&Transcode> {
+        self.transcode.as_ref()
+    }
+
+    /// The storage key of the stored representation, which is converted from if necessary.
+    pub fn source_key(&self) -> PathBuf {
+        match &self.transcode {
+            None => self.storage_key.clone(),
+            Some(transcode) => self.storage_key.with_extension(&transcode.extension),
+        }
+    }
+
+    /// The file extension of the storage key, preceded by the language tag if there is one.
+    /// A converted representation has the extension of the representation it is converted from.
     pub fn storage_extension(&self) -> std::borrow::Cow<'_, str> {
-        self.storage_key.extension().unwrap().to_string_lossy()
+        if let Some(transcode) = &self.transcode {
+            return transcode.extension.as_str().into();
+        }
+        let ext = self.storage_key.extension().unwrap().to_string_lossy();
+        match &self.language {
+            None => ext,
+            Some(language) => format!("{language}.{ext}").into(),
+        }
     }
 
+    /// Parameters such as *charset* are included along with the essence of the media type.
     pub fn content_type_header(&self) -> HeaderValue {
-        HeaderValue::from_str(self.media_type.essence().to_string().as_str()).unwrap()
+        HeaderValue::from_str(self.media_type.to_string().as_str()).unwrap()
     }
 
     pub fn content_location_header(&self) -> HeaderValue {
-        let file_name = match self.storage_extension().deref() {
+        let file_name = match self
+            .storage_key
+            .extension()
+            .unwrap()
+            .to_string_lossy()
+            .deref()
+        {
             ext if ext == Self::GENERIC_EXT => self.storage_key.file_stem(),
             _ => self.storage_key.file_name(),
         };
@@ -162,39 +302,129 @@ impl<'a> AsRef<Path> for NegotiatedPath<'a> {
     }
 }
 
+/// Name of the extension attribute holding the language tag of a translation.
+pub const LANGUAGE: &str = "language";
+
+pub struct Representation<'a> {
+    pub extension: &'a str,
+    pub media_type: MediaType<'a>,
+    pub language: Option<&'a str>,
+    pub transcode: Option<Transcode>,
+}
+
 pub struct PathExtensions {
+    /// Storage key of the extensions.
     pub path: PathBuf,
+    /// Storage key of the representations, without an extension.
+    stem: PathBuf,
     map: serde_json::Map<String, serde_json::Value>,
 }
 
 impl PathExtensions {
-    pub const META_EXT: &str = "ext";
+    /// Storage keys of users always start with "/", so keys in this namespace can not be addressed.
+    /// The extensions of a path are stored under its stem, such as "extensions/about".
+    pub const NAMESPACE: &str = "extensions";
+
+    /// Storage key of the extensions stored for `path`.
+    pub fn storage_key(path: &Path) -> PathBuf {
+        Self::key_prefix("", &Path::new("/").join(crate::util::path_stem(path)))
+    }
+
+    /// Storage keys of the extensions of paths starting with `prefix`, in an internal `namespace`
+    /// which mirrors the user namespace, or in the user namespace itself if it is empty.
+    pub fn key_prefix(namespace: &str, prefix: &Path) -> PathBuf {
+        PathBuf::from(format!(
+            "{}{}",
+            Self::namespace_in(namespace),
+            prefix.to_string_lossy()
+        ))
+    }
+
+    /// The path of which the extensions are stored at `key`, the inverse of `key_prefix`.
+    pub fn path_of(namespace: &str, key: &Path) -> Option<PathBuf> {
+        let path = key.to_str()?.strip_prefix(&Self::namespace_in(namespace))?;
+        path.starts_with('/').then(|| PathBuf::from(path))
+    }
+
+    /// The extensions namespace within an internal `namespace`, such as "trash:extensions".
+    /// The delimiter keeps it apart from the keys of that namespace, which start with "/".
+    fn namespace_in(namespace: &str) -> String {
+        match namespace {
+            "" => Self::NAMESPACE.to_string(),
+            namespace => format!("{namespace}:{}", Self::NAMESPACE),
+        }
+    }
+
+    /// Paths of every resource with stored extensions at or below `prefix`.
+    pub fn resources(prefix: &Path, db: Arc<impl StorageBackend>) -> Result<Vec<PathBuf>> {
+        let keys = db.keys(Self::key_prefix("", prefix))?;
+        Ok(keys
+            .iter()
+            .filter_map(|key| Self::path_of("", key))
+            // a prefix such as "/doc" does not contain "/docs"
+            .filter(|path| path.starts_with(prefix))
+            .collect())
+    }
 
     /// Instantiate from storage backend.
     pub fn get_for_path(path: &Path, db: Arc<impl StorageBackend>) -> Self {
-        let path = Path::new("/")
-            .join(crate::util::path_stem(path))
-            .with_extension(Self::META_EXT);
+        Self::get_in("", path, db)
+    }
+
+    /// Instantiate from storage backend, for `path` in an internal `namespace`
+    /// which mirrors the user namespace, such as the trash.
+    pub fn get_in(namespace: &str, path: &Path, db: Arc<impl StorageBackend>) -> Self {
+        let stem = Path::new("/").join(crate::util::path_stem(path));
+        let path = Self::key_prefix(namespace, &stem);
         let map = db
             .get(&path)
             .ok()
             .flatten()
             .and_then(|s| serde_json::from_slice(s.as_slice()).ok())
             .unwrap_or_default();
-        Self { path, map }
+        let stem = PathBuf::from(format!("{namespace}{}", stem.to_string_lossy()));
+        Self { path, stem, map }
+    }
+
+    /// Storage key of the representation with `extension`.
+    pub fn key_of(&self, extension: &str) -> PathBuf {
+        self.stem.with_extension(extension)
     }
 
     /// Returns a description of the storage operation to perform in a batch update.
     pub fn insert(&mut self, negotiated: &NegotiatedPath) -> Result<(&Path, Option<Vec<u8>>)> {
-        self.map.insert(
-            negotiated
-                .storage_key
-                .extension()
-                .unwrap()
-                .to_string_lossy()
-                .to_string(),
-            serde_json::Value::String(negotiated.media_type.to_string()),
-        );
+        self.insert_with(negotiated, serde_json::Map::new())
+    }
+
+    /// Returns a description of the storage operation to perform in a batch update.
+    /// The `attributes` are stored along with the media type of the representation.
+    pub fn insert_with(
+        &mut self,
+        negotiated: &NegotiatedPath,
+        mut attributes: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<(&Path, Option<Vec<u8>>)> {
+        let media_type = serde_json::Value::String(negotiated.media_type.to_string());
+        if let Some(language) = &negotiated.language {
+            attributes.insert(LANGUAGE.to_string(), language.clone().into());
+        }
+        let entry = if attributes.is_empty() {
+            media_type
+        } else {
+            attributes.insert("type".to_string(), media_type);
+            serde_json::Value::Object(attributes)
+        };
+        self.insert_entry(&negotiated.storage_extension(), entry)
+    }
+
+    /// Returns a description of the storage operation to perform in a batch update.
+    /// An entry is either a media type string, or an object with the media type
+    /// under "type" and additional attributes of the representation.
+    pub fn insert_entry(
+        &mut self,
+        extension: &str,
+        entry: serde_json::Value,
+    ) -> Result<(&Path, Option<Vec<u8>>)> {
+        self.map.insert(extension.to_string(), entry);
         let map_string = serde_json::to_string(&self.map)?;
         Ok((&self.path, Some(map_string.into_bytes())))
     }
@@ -212,48 +442,375 @@ impl PathExtensions {
         }
     }
 
-    fn get_media_type(&self, extension: &str) -> Result<Option<MediaType<'_>>> {
+    /// Returns a description of the storage operations which remove the representations
+    /// with `extensions` along with their entries.
+    pub fn remove_representations(
+        &mut self,
+        extensions: &[String],
+    ) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+        let mut updates: Vec<(PathBuf, Option<Vec<u8>>)> = extensions
+            .iter()
+            .map(|ext| (self.key_of(ext), None))
+            .collect();
+        let mut extensions_update = None;
+        for ext in extensions {
+            let (k, v) = self.remove(ext)?;
+            extensions_update = Some((k.to_owned(), v));
+        }
+        updates.extend(extensions_update);
+        Ok(updates)
+    }
+
+    /// Expired representations are treated as if they did not exist.
+    pub fn get_media_type(&self, extension: &str) -> Result<Option<MediaType<'_>>> {
         match self.map.get(extension) {
-            Some(v) => match v {
-                serde_json::Value::String(mt) => MediaTypeString(mt).try_into().map(Some),
-                other => bail!("{extension}: {other:?} (should be string)"),
+            Some(v) if is_expired(v) => Ok(None),
+            Some(v) => match entry_media_type(v) {
+                Some(mt) => MediaTypeString(mt).try_into().map(Some),
+                None => bail!("{extension}: {v:?} (should be string or have string type)"),
             },
             None => Ok(None),
         }
     }
 
-    fn get_all_media_types(&self) -> Result<Vec<MediaType<'_>>> {
-        let mut mt_strings: Vec<MediaTypeString> = self
-            .map
-            .iter()
-            .filter_map(|(_, v)| match v {
-                serde_json::Value::String(mt) => Some(MediaTypeString(mt)),
-                _ => None,
-            })
-            .collect();
-        mt_strings.sort_by(|a, b| {
-            let first = "application/json";
-            if a.0.starts_with(first) {
-                Ordering::Less
-            } else if b.0.starts_with(first) {
-                Ordering::Greater
-            } else {
-                Ordering::Equal
+    pub fn get_entry(&self, extension: &str) -> Option<&serde_json::Value> {
+        self.map.get(extension)
+    }
+
+    /// The value of an additional attribute of the representation with `extension`.
+    pub fn get_attribute(&self, extension: &str, name: &str) -> Option<&serde_json::Value> {
+        match self.map.get(extension) {
+            Some(serde_json::Value::Object(attributes)) => attributes.get(name),
+            _ => None,
+        }
+    }
+
+    pub fn get_expiry(&self, extension: &str) -> Option<SystemTime> {
+        let expires = self.get_attribute(extension, crate::expiry::ATTRIBUTE)?;
+        expires.as_u64().map(crate::util::from_micros)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &serde_json::Value)> {
+        self.map.iter()
+    }
+
+    pub fn get_language(&self, extension: &str) -> Option<&str> {
+        self.get_attribute(extension, LANGUAGE)?.as_str()
+    }
+
+    /// The extension under which the representation stored at `key` is recorded,
+    /// which includes the language tag of a translation such as `/about.fr.html`.
+    pub fn extension_of(&self, key: &Path) -> Option<String> {
+        let ext = key.extension()?.to_string_lossy();
+        if let Some(language) = Path::new(key.file_stem()?).extension() {
+            let tagged = format!("{}.{ext}", language.to_string_lossy());
+            if self.get_language(&tagged).is_some() {
+                return Some(tagged);
             }
-        });
-        mt_strings.into_iter().map(|mt| mt.try_into()).collect()
+        }
+        Some(ext.to_string())
     }
 
-    fn get_extension(&self, media_type: &MediaType<'_>) -> Result<Option<&str>> {
-        for (k, v) in self.map.iter() {
-            if let serde_json::Value::String(mt) = v {
-                let mt: MediaType<'_> = MediaTypeString(mt).try_into()?;
-                if mt == *media_type {
-                    return Ok(Some(k.as_str()));
-                }
+    /// The path at which the representation with `extension` can be requested.
+    pub fn location_of(&self, extension: &str) -> PathBuf {
+        match extension {
+            NegotiatedPath::GENERIC_EXT => self.stem.clone(),
+            ext => self.key_of(ext),
+        }
+    }
+
+    /// Every representation which has not expired.
+    pub fn representations(&self) -> Result<Vec<Representation<'_>>> {
+        let mut representations = vec![];
+        for (ext, v) in self.map.iter().filter(|(_, v)| !is_expired(v)) {
+            if let Some(mt) = entry_media_type(v) {
+                representations.push(Representation {
+                    extension: ext.as_str(),
+                    media_type: MediaTypeString(mt).try_into()?,
+                    language: self.get_language(ext),
+                    transcode: None,
+                });
             }
         }
-        Ok(None)
+        Ok(representations)
+    }
+}
+
+/// Response for a request which none of the available representations satisfy.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-15.5.7
+pub fn not_acceptable(
+    extensions: &PathExtensions,
+) -> Result<(StatusCode, Option<HeaderMap>, Option<Bytes>)> {
+    listing(StatusCode::NOT_ACCEPTABLE, extensions)
+}
+
+/// Response for a request without preferences, when there is no default representation.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-15.4.1
+pub fn multiple_choices(
+    extensions: &PathExtensions,
+) -> Result<(StatusCode, Option<HeaderMap>, Option<Bytes>)> {
+    listing(StatusCode::MULTIPLE_CHOICES, extensions)
+}
+
+/// *link* header values pointing at each representation other than the one with `extension`.
+/// https://www.rfc-editor.org/rfc/rfc8288.html
+pub fn alternates(extensions: &PathExtensions, extension: &str) -> Result<Vec<HeaderValue>> {
+    let mut links = vec![];
+    for representation in extensions.representations()? {
+        if representation.extension == extension {
+            continue;
+        }
+        let mut link = format!(
+            "<{}>; rel=\"alternate\"; type=\"{}\"",
+            extensions
+                .location_of(representation.extension)
+                .to_string_lossy(),
+            representation.media_type
+        );
+        if let Some(language) = representation.language {
+            link.push_str(&format!("; hreflang=\"{language}\""));
+        }
+        links.push(HeaderValue::from_str(&link)?);
+    }
+    Ok(links)
+}
+
+/// The body lists the location and media type of each representation,
+/// so that the client can pick one.
+fn listing(
+    status: StatusCode,
+    extensions: &PathExtensions,
+) -> Result<(StatusCode, Option<HeaderMap>, Option<Bytes>)> {
+    let mut listing = vec![];
+    for representation in extensions.representations()? {
+        let location = extensions.location_of(representation.extension);
+        let mut item = serde_json::json!({
+            "location": location.to_string_lossy(),
+            "content-type": representation.media_type.to_string(),
+        });
+        if let Some(language) = representation.language {
+            item["content-language"] = language.into();
+        }
+        listing.push(item);
+    }
+
+    let listing = serde_json::to_vec(&listing)?;
+    let mut headers = HeaderMap::new();
+    headers.append(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.append(header::CONTENT_LENGTH, listing.len().into());
+    headers.append(header::VARY, HeaderValue::from_static("accept"));
+    for link in alternates(extensions, "")? {
+        headers.append(header::LINK, link);
+    }
+    Ok((status, Some(headers), Some(listing.into())))
+}
+
+/// Order the acceptable representations in `available` from most to least preferred.
+/// Each is scored by the product of the quality assigned by the most specific matching range
+/// of the client's `accept` and of the server's `preferred` ranges, both sorted by specificity.
+/// Ties go to a stored rather than converted representation,
+/// then the one matched by the more specific range of `accept`,
+/// then the one matched by the range listed first, then the generic representation,
+/// and finally the lowest file extension, so that the result is deterministic.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-12.5.1
+fn rank<'a>(
+    acceptable: &Acceptable,
+    preferred: &[MediaTypeBuf],
+    available: Vec<Representation<'a>>,
+) -> Vec<Representation<'a>> {
+    let mut ranked = vec![];
+    for representation in available {
+        let media_type = &representation.media_type;
+        let Some(client) = best_match(&acceptable.media_ranges, media_type) else {
+            continue;
+        };
+        let server = best_match(preferred, media_type).map_or(1.0, |m| m.quality);
+        let score = client.quality
+            * acceptable.charset_quality(media_type)
+            * acceptable.language_quality(representation.language)
+            * server;
+        if score > 0.0 {
+            ranked.push((score, client, representation));
+        }
+    }
+
+    ranked.sort_by(|(a_score, a, a_rep), (b_score, b, b_rep)| {
+        let (a_ext, b_ext) = (a_rep.extension, b_rep.extension);
+        b_score
+            .total_cmp(a_score)
+            .then(a_rep.transcode.is_some().cmp(&b_rep.transcode.is_some()))
+            .then(b.specificity.cmp(&a.specificity))
+            .then(a.position.cmp(&b.position))
+            .then(
+                (b_ext == NegotiatedPath::GENERIC_EXT).cmp(&(a_ext == NegotiatedPath::GENERIC_EXT)),
+            )
+            .then(a_ext.cmp(b_ext))
+    });
+    ranked
+        .into_iter()
+        .map(|(_, _, representation)| representation)
+        .collect()
+}
+
+/// The proactive negotiation headers of a request.
+struct Acceptable {
+    /// Ordered by specificity.
+    media_ranges: Vec<MediaTypeBuf>,
+    charsets: Vec<(String, f32)>,
+    languages: Vec<(String, f32)>,
+}
+
+impl Acceptable {
+    /// Quality of a representation without a language tag when the client has a preference,
+    /// so that it is only selected if no translation is acceptable.
+    const UNTAGGED_LANGUAGE_QUALITY: f32 = 0.001;
+
+    /// Any media type is acceptable without an `accept` header.
+    fn from_headers(accept: Option<&HeaderValue>, headers: &HeaderMap) -> Result<Self> {
+        let accept = match accept {
+            Some(accept) => accept.to_str()?,
+            None => "*/*",
+        };
+        let media_ranges = Accept::from_str(accept)?.media_types().cloned().collect();
+        let mut charsets = vec![];
+        for value in headers.get_all(header::ACCEPT_CHARSET) {
+            charsets.extend(quality_list(value.to_str()?));
+        }
+        let mut languages = vec![];
+        for value in headers.get_all(header::ACCEPT_LANGUAGE) {
+            languages.extend(quality_list(value.to_str()?));
+        }
+        Ok(Self {
+            media_ranges,
+            charsets,
+            languages,
+        })
+    }
+
+    /// The longest matching language range applies, using basic filtering.
+    /// https://www.rfc-editor.org/rfc/rfc4647.html#section-3.3.1
+    fn language_quality(&self, language: Option<&str>) -> f32 {
+        if self.languages.is_empty() {
+            return 1.0;
+        }
+        let Some(language) = language else {
+            return Self::UNTAGGED_LANGUAGE_QUALITY;
+        };
+        self.languages
+            .iter()
+            .filter(|(range, _)| {
+                range == "*"
+                    || language.eq_ignore_ascii_case(range)
+                    || language
+                        .get(..range.len() + 1)
+                        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(&format!("{range}-")))
+            })
+            .max_by_key(|(range, _)| if range == "*" { 0 } else { range.len() })
+            .map_or(0.0, |(_, q)| *q)
+    }
+
+    /// Representations without a *charset* parameter are acceptable in any case.
+    /// https://www.rfc-editor.org/rfc/rfc9110.html#section-12.5.2
+    fn charset_quality(&self, media_type: &MediaType) -> f32 {
+        let Some(charset) = media_type.get_param(CHARSET) else {
+            return 1.0;
+        };
+        if self.charsets.is_empty() {
+            return 1.0;
+        }
+        self.charsets
+            .iter()
+            .find(|(token, _)| token.eq_ignore_ascii_case(charset.as_str()))
+            .or_else(|| self.charsets.iter().find(|(token, _)| token == "*"))
+            .map_or(0.0, |(_, q)| *q)
+    }
+}
+
+/// Parse a comma separated list of tokens with optional quality values, such as *accept-charset*.
+fn quality_list(value: &str) -> Vec<(String, f32)> {
+    let mut list = vec![];
+    for item in value.split(',') {
+        let mut params = item.split(';').map(str::trim);
+        let token = params.next().unwrap_or_default();
+        if token.is_empty() {
+            continue;
+        }
+        let q = params
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        list.push((token.to_string(), q));
+    }
+    list
+}
+
+struct RangeMatch {
+    quality: f32,
+    specificity: usize,
+    position: usize,
+}
+
+/// The first of `ranges` which matches `media_type`.
+fn best_match(ranges: &[MediaTypeBuf], media_type: &MediaType) -> Option<RangeMatch> {
+    let (position, range) = ranges
+        .iter()
+        .enumerate()
+        .find(|(_, range)| range_matches(&range.to_ref(), media_type))?;
+    let quality = range
+        .get_param(Q)
+        .and_then(|q| q.as_str().parse::<f32>().ok())
+        .unwrap_or(1.0);
+    let specificity = usize::from(range.ty() != _STAR)
+        + usize::from(range.subty() != _STAR)
+        + range.params().filter(|(name, _)| *name != Q).count();
+    Some(RangeMatch {
+        quality,
+        specificity,
+        position,
+    })
+}
+
+fn range_matches(range: &MediaType, media_type: &MediaType) -> bool {
+    (range.ty == _STAR || range.ty == media_type.ty)
+        && (range.subty == _STAR
+            || (range.subty == media_type.subty && range.suffix == media_type.suffix))
+        && range
+            .params()
+            .filter(|(name, _)| *name != Q)
+            .all(|(name, value)| media_type.get_param(name) == Some(value))
+}
+
+/// A single language tag in the *content-language* header, in lower case.
+fn content_language(headers: &HeaderMap) -> Option<String> {
+    let tag = headers.get(header::CONTENT_LANGUAGE)?.to_str().ok()?.trim();
+    let valid = tag.split('-').enumerate().all(|(i, subtag)| {
+        (1..=8).contains(&subtag.len())
+            && match i {
+                0 => subtag.chars().all(|c| c.is_ascii_alphabetic()),
+                _ => subtag.chars().all(|c| c.is_ascii_alphanumeric()),
+            }
+    });
+    valid.then(|| tag.to_ascii_lowercase())
+}
+
+fn entry_media_type(entry: &serde_json::Value) -> Option<&String> {
+    match entry {
+        serde_json::Value::String(mt) => Some(mt),
+        serde_json::Value::Object(attributes) => match attributes.get("type") {
+            Some(serde_json::Value::String(mt)) => Some(mt),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_expired(entry: &serde_json::Value) -> bool {
+    match entry.get(crate::expiry::ATTRIBUTE).and_then(|e| e.as_u64()) {
+        Some(expires) => crate::util::from_micros(expires) <= SystemTime::now(),
+        None => false,
     }
 }
 
@@ -269,3 +826,251 @@ impl<'a> TryInto<MediaType<'a>> for MediaTypeString<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TypePreferences;
+
+    fn ranked(
+        headers: &[(&'static str, &'static str)],
+        preferred: &str,
+        available: &[(&'static str, &'static str)],
+    ) -> Vec<&'static str> {
+        let headers = HeaderMap::from_iter(headers.iter().map(|(name, value)| {
+            (
+                header::HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            )
+        }));
+        let acceptable = Acceptable::from_headers(headers.get(header::ACCEPT), &headers).unwrap();
+        let preferred = TypePreferences::try_from_str(preferred).unwrap();
+        let available = available
+            .iter()
+            .map(|(ext, mt)| Representation {
+                extension: ext,
+                media_type: MediaType::parse(mt).unwrap(),
+                language: ext.split_once('.').map(|(language, _)| language),
+                transcode: None,
+            })
+            .collect();
+        rank(&acceptable, preferred.for_path(Path::new("/")), available)
+            .into_iter()
+            .map(|r| r.extension)
+            .collect()
+    }
+
+    #[test]
+    fn test_rank() {
+        let available = [
+            ("xml", "application/xml"),
+            ("json", "application/json"),
+            ("html", "text/html"),
+            ("txt", "text/plain"),
+            (NegotiatedPath::GENERIC_EXT, "application/octet-stream"),
+        ];
+
+        // without preferences, wildcards favour the generic type and then the extension
+        assert_eq!(
+            ranked(&[("accept", "*/*")], "", &available),
+            ["octet-stream", "html", "json", "txt", "xml"]
+        );
+        assert_eq!(
+            ranked(&[("accept", "text/*")], "", &available),
+            ["html", "txt"]
+        );
+
+        // server preferences break the tie of a wildcard
+        assert_eq!(
+            ranked(
+                &[("accept", "*/*")],
+                "text/html, application/*;q=0.5, */*;q=0.1",
+                &available
+            ),
+            ["html", "octet-stream", "json", "xml", "txt"]
+        );
+
+        // client quality outweighs server preference
+        assert_eq!(
+            ranked(
+                &[("accept", "application/json, text/html;q=0.4")],
+                "text/html, */*;q=0.5",
+                &available
+            ),
+            ["json", "html"]
+        );
+
+        // the most specific range applies
+        assert_eq!(
+            ranked(
+                &[("accept", "text/*, text/plain;q=0, */*;q=0.1")],
+                "",
+                &available
+            ),
+            ["html", "octet-stream", "json", "xml"]
+        );
+
+        // equally specific ranges of equal quality are taken in order
+        assert_eq!(
+            ranked(&[("accept", "text/plain, text/html")], "", &available),
+            ["txt", "html"]
+        );
+
+        // parameters must match
+        let available = [("txt", "text/plain; charset=utf-8"), ("csv", "text/csv")];
+        assert_eq!(
+            ranked(&[("accept", "text/*;charset=utf-8")], "", &available),
+            ["txt"]
+        );
+        assert_eq!(
+            ranked(&[("accept", "text/plain;charset=latin1")], "", &available),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn test_rank_charset() {
+        let available = [
+            ("latin1", "text/plain; charset=iso-8859-1"),
+            ("txt", "text/plain; charset=utf-8"),
+            ("bin", "application/octet-stream"),
+        ];
+
+        assert_eq!(
+            ranked(&[("accept", "text/plain")], "", &available),
+            ["latin1", "txt"]
+        );
+        assert_eq!(
+            ranked(
+                &[
+                    ("accept", "*/*"),
+                    ("accept-charset", "utf-8, iso-8859-1;q=0.5")
+                ],
+                "",
+                &available
+            ),
+            ["bin", "txt", "latin1"]
+        );
+        assert_eq!(
+            ranked(
+                &[("accept", "text/plain"), ("accept-charset", "ISO-8859-1")],
+                "",
+                &available
+            ),
+            ["latin1"]
+        );
+        assert_eq!(
+            ranked(
+                &[
+                    ("accept", "text/plain"),
+                    ("accept-charset", "utf-16, *;q=0.1")
+                ],
+                "",
+                &available
+            ),
+            ["latin1", "txt"]
+        );
+    }
+
+    #[test]
+    fn test_rank_language() {
+        let available = [
+            ("en.html", "text/html"),
+            ("fr.html", "text/html"),
+            ("fr-ca.html", "text/html"),
+            ("html", "text/html"),
+        ];
+
+        assert_eq!(
+            ranked(&[("accept", "text/html")], "", &available),
+            ["en.html", "fr-ca.html", "fr.html", "html"]
+        );
+        // the longest matching range applies, untagged representations come last
+        assert_eq!(
+            ranked(
+                &[
+                    ("accept", "*/*"),
+                    ("accept-language", "fr-CA, fr;q=0.8, en;q=0.5")
+                ],
+                "",
+                &available
+            ),
+            ["fr-ca.html", "fr.html", "en.html", "html"]
+        );
+        assert_eq!(
+            ranked(
+                &[("accept", "*/*"), ("accept-language", "fr;q=0.8, *;q=0.1")],
+                "",
+                &available
+            ),
+            ["fr-ca.html", "fr.html", "en.html", "html"]
+        );
+        assert_eq!(
+            ranked(
+                &[("accept", "*/*"), ("accept-language", "de")],
+                "",
+                &available
+            ),
+            ["html"]
+        );
+    }
+
+    #[test]
+    fn test_content_language() {
+        let headers = |value| {
+            HeaderMap::from_iter([(header::CONTENT_LANGUAGE, HeaderValue::from_static(value))])
+        };
+        assert_eq!(
+            content_language(&headers("fr-CA")),
+            Some("fr-ca".to_string())
+        );
+        assert_eq!(content_language(&headers("en, fr")), None);
+        assert_eq!(content_language(&headers("fr.html")), None);
+        assert_eq!(content_language(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_extensions_key() {
+        let key = PathExtensions::storage_key(Path::new("/docs/about.fr.html"));
+        assert_eq!(key, PathBuf::from("extensions/docs/about"));
+        assert_eq!(
+            PathExtensions::path_of("", &key),
+            Some(PathBuf::from("/docs/about"))
+        );
+
+        let key = PathExtensions::key_prefix("trash", Path::new("/docs/about"));
+        assert_eq!(key, PathBuf::from("trash:extensions/docs/about"));
+        assert_eq!(
+            PathExtensions::path_of("trash", &key),
+            Some(PathBuf::from("/docs/about"))
+        );
+        assert_eq!(PathExtensions::path_of("", &key), None);
+        assert_eq!(PathExtensions::path_of("", Path::new("/about.ext")), None);
+    }
+
+    #[test]
+    fn test_alternates() {
+        let extensions = PathExtensions {
+            path: PathBuf::from("extensions/about"),
+            stem: PathBuf::from("/about"),
+            map: serde_json::from_str(
+                r#"{
+                    "octet-stream": "application/octet-stream",
+                    "json": "application/json",
+                    "fr.html": {"type": "text/html", "language": "fr"}
+                }"#,
+            )
+            .unwrap(),
+        };
+
+        let links = alternates(&extensions, "json").unwrap();
+        assert_eq!(
+            links,
+            [
+                r#"</about.fr.html>; rel="alternate"; type="text/html"; hreflang="fr""#,
+                r#"</about>; rel="alternate"; type="application/octet-stream""#,
+            ]
+        );
+        assert_eq!(alternates(&extensions, "").unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::Result;
+use http::{HeaderMap, HeaderName, HeaderValue, Method, header};
+
+/// Response headers which scripts of other origins can read.
+pub const EXPOSED_HEADERS: &str = "content-location, etag";
+
+/// Seconds a browser can cache the result of a preflight request.
+const MAX_AGE: u32 = 600;
+
+/// Cross-origin resource sharing with browser apps of the allowed origins.
+/// https://fetch.spec.whatwg.org/#http-cors-protocol
+#[derive(Debug, Clone, Default)]
+pub struct Cors {
+    /// Every origin is allowed if this contains "*", and none if it is empty.
+    origins: Vec<String>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+}
+
+impl Cors {
+    pub const DEFAULT_METHODS: &str = "GET, HEAD, PUT, DELETE";
+    pub const DEFAULT_HEADERS: &str = "authorization, content-type, if-match, if-none-match";
+
+    /// Lists of `origins`, `methods` and `headers` are separated by commas or spaces.
+    pub fn try_new(origins: &str, methods: Option<&str>, headers: Option<&str>) -> Result<Self> {
+        let list = |list: &str| -> Vec<String> {
+            list.split([',', ' '])
+                .filter(|item| !item.is_empty())
+                .map(|item| item.to_string())
+                .collect()
+        };
+        let methods = list(methods.unwrap_or(Self::DEFAULT_METHODS))
+            .iter()
+            .map(|m| Method::from_bytes(m.to_ascii_uppercase().as_bytes()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let headers = list(headers.unwrap_or(Self::DEFAULT_HEADERS))
+            .iter()
+            .map(|h| HeaderName::from_bytes(h.to_ascii_lowercase().as_bytes()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(Self {
+            origins: list(origins),
+            methods,
+            headers,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.origins.is_empty()
+    }
+
+    /// Headers of every response to a request from `headers`,
+    /// allowing its origin to read the response if it is allowed.
+    pub fn response_headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut cors = HeaderMap::new();
+        if !self.is_active() {
+            return cors;
+        }
+        let any_origin = self.origins.iter().any(|o| o == "*");
+        if !any_origin {
+            // the response depends on the origin of the request
+            cors.append(header::VARY, HeaderValue::from_static("origin"));
+        }
+        let Some(origin) = headers.get(header::ORIGIN) else {
+            return cors;
+        };
+        let allowed = any_origin
+            || self
+                .origins
+                .iter()
+                .any(|o| o.as_bytes().eq_ignore_ascii_case(origin.as_bytes()));
+        if allowed {
+            let allowed_origin = match any_origin {
+                true => HeaderValue::from_static("*"),
+                false => origin.clone(),
+            };
+            cors.append(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed_origin);
+            cors.append(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_static(EXPOSED_HEADERS),
+            );
+        }
+        cors
+    }
+
+    /// Headers of the response to a preflight request, if `method` and `headers` make one.
+    /// They are empty if the requested method or headers are not allowed.
+    pub fn preflight(&self, method: &Method, headers: &HeaderMap) -> Option<HeaderMap> {
+        if !self.is_active() || method != Method::OPTIONS || !headers.contains_key(header::ORIGIN) {
+            return None;
+        }
+        let requested_method = headers.get(header::ACCESS_CONTROL_REQUEST_METHOD)?;
+
+        let mut preflight = HeaderMap::new();
+        let method_allowed = Method::from_bytes(requested_method.as_bytes())
+            .is_ok_and(|m| self.methods.contains(&m));
+        let mut requested_headers = headers
+            .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty());
+        let headers_allowed =
+            requested_headers.all(|name| self.headers.iter().any(|h| h.as_str() == name));
+        if !method_allowed || !headers_allowed {
+            return Some(preflight);
+        }
+
+        let methods: Vec<&str> = self.methods.iter().map(|m| m.as_str()).collect();
+        let allowed_headers: Vec<&str> = self.headers.iter().map(|h| h.as_str()).collect();
+        preflight.append(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_str(&methods.join(", ")).ok()?,
+        );
+        if !allowed_headers.is_empty() {
+            preflight.append(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                HeaderValue::from_str(&allowed_headers.join(", ")).ok()?,
+            );
+        }
+        preflight.append(header::ACCESS_CONTROL_MAX_AGE, MAX_AGE.into());
+        Some(preflight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(pairs: &[(HeaderName, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_response_headers() {
+        let cors = Cors::try_new("https://app.example.com", None, None).unwrap();
+        let allowed =
+            cors.response_headers(&request(&[(header::ORIGIN, "https://app.example.com")]));
+        assert_eq!(
+            allowed[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(
+            allowed[header::ACCESS_CONTROL_EXPOSE_HEADERS],
+            EXPOSED_HEADERS
+        );
+        assert_eq!(allowed[header::VARY], "origin");
+
+        let refused = cors.response_headers(&request(&[(header::ORIGIN, "https://other.example")]));
+        assert!(!refused.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let any = Cors::try_new("*", None, None).unwrap();
+        let allowed = any.response_headers(&request(&[(header::ORIGIN, "https://other.example")]));
+        assert_eq!(allowed[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!allowed.contains_key(header::VARY));
+        assert!(Cors::default().response_headers(&allowed).is_empty());
+    }
+
+    #[test]
+    fn test_preflight() {
+        let cors = Cors::try_new("*", Some("get,put"), Some("content-type")).unwrap();
+        let preflight = |pairs: &[(HeaderName, &'static str)]| {
+            cors.preflight(&Method::OPTIONS, &request(pairs))
+        };
+
+        let allowed = preflight(&[
+            (header::ORIGIN, "https://app.example.com"),
+            (header::ACCESS_CONTROL_REQUEST_METHOD, "PUT"),
+            (header::ACCESS_CONTROL_REQUEST_HEADERS, "Content-Type"),
+        ])
+        .unwrap();
+        assert_eq!(allowed[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+        assert_eq!(
+            allowed[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+
+        let refused = preflight(&[
+            (header::ORIGIN, "https://app.example.com"),
+            (header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE"),
+        ])
+        .unwrap();
+        assert!(refused.is_empty());
+        let refused = preflight(&[
+            (header::ORIGIN, "https://app.example.com"),
+            (header::ACCESS_CONTROL_REQUEST_METHOD, "PUT"),
+            (header::ACCESS_CONTROL_REQUEST_HEADERS, "x-custom"),
+        ])
+        .unwrap();
+        assert!(refused.is_empty());
+
+        assert!(preflight(&[(header::ORIGIN, "https://app.example.com")]).is_none());
+        assert!(
+            cors.preflight(
+                &Method::GET,
+                &request(&[(header::ORIGIN, "https://a.example")])
+            )
+            .is_none()
+        );
+    }
+}
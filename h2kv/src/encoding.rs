@@ -0,0 +1,327 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+use mediatype::MediaType;
+
+use crate::storage::StorageBackend;
+
+/// Storage keys of users always start with "/", so keys in this namespace can not be addressed.
+/// Pre-compressed representations are stored after the key of the uncompressed representation
+/// and named after its digest, so that a stale variant is never served.
+pub const NAMESPACE: &str = "encoded";
+
+/// Content codings supported for requests and responses.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-8.4.1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Coding {
+    /// In order of preference when the client has none.
+    pub const ALL: [Coding; 3] = [Coding::Brotli, Coding::Zstd, Coding::Gzip];
+
+    pub fn token(&self) -> &'static str {
+        match self {
+            Coding::Brotli => "br",
+            Coding::Zstd => "zstd",
+            Coding::Gzip => "gzip",
+        }
+    }
+
+    pub fn from_token(token: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| {
+            c.token().eq_ignore_ascii_case(token)
+                || (*c == Coding::Gzip && token.eq_ignore_ascii_case("x-gzip"))
+        })
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoded = vec![];
+        match self {
+            Coding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(&mut encoded, 4096, 6, 22);
+                writer.write_all(data)?;
+            }
+            Coding::Zstd => encoded = zstd::encode_all(data, 0)?,
+            Coding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(&mut encoded, flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?;
+            }
+        }
+        Ok(encoded)
+    }
+
+    /// Decode `data`, unless it decodes to more than `limit` bytes.
+    pub fn decode(&self, data: &[u8], limit: usize) -> Result<Option<Vec<u8>>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Coding::Brotli => Box::new(brotli::Decompressor::new(data, 4096)),
+            Coding::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+            Coding::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+        };
+        // reading stops one byte past the limit, however much the data would expand
+        let mut decoded = vec![];
+        decoder.take(limit as u64 + 1).read_to_end(&mut decoded)?;
+        Ok((decoded.len() <= limit).then_some(decoded))
+    }
+}
+
+/// Decode a request body according to its *content-encoding* header, to at most `limit` bytes.
+/// If a coding is not supported, or the decoded body is too large, the status refusing it is returned.
+pub fn decode_body(
+    headers: &HeaderMap,
+    body: Vec<u8>,
+    limit: usize,
+) -> Result<std::result::Result<Vec<u8>, StatusCode>> {
+    let mut codings = vec![];
+    for value in headers.get_all(header::CONTENT_ENCODING) {
+        for token in value.to_str()?.split(',').map(str::trim) {
+            match (token, Coding::from_token(token)) {
+                ("" | "identity", _) => (),
+                (_, Some(coding)) => codings.push(coding),
+                (_, None) => return Ok(Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)),
+            }
+        }
+    }
+
+    // codings are listed in the order in which they were applied
+    let mut body = body;
+    for coding in codings.into_iter().rev() {
+        match coding.decode(&body, limit)? {
+            Some(decoded) => body = decoded,
+            None => return Ok(Err(StatusCode::PAYLOAD_TOO_LARGE)),
+        }
+    }
+    Ok(Ok(body))
+}
+
+/// Select a content coding acceptable to the client, if the media type is worth compressing.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-12.5.3
+pub fn negotiate(headers: &HeaderMap, media_type: &MediaType) -> Option<Coding> {
+    if !is_compressible(media_type) {
+        return None;
+    }
+
+    let mut preferences: Vec<(&str, f32)> = vec![];
+    for value in headers.get_all(header::ACCEPT_ENCODING) {
+        let Ok(value) = value.to_str() else { continue };
+        for item in value.split(',') {
+            let mut params = item.split(';').map(str::trim);
+            let token = params.next().unwrap_or_default();
+            let q = params
+                .find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            preferences.push((token, q));
+        }
+    }
+
+    let quality = |coding: Coding| -> f32 {
+        preferences
+            .iter()
+            .find(|(token, _)| Coding::from_token(token) == Some(coding))
+            .or_else(|| preferences.iter().find(|(token, _)| *token == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or(0.0)
+    };
+
+    let mut best: Option<(Coding, f32)> = None;
+    for coding in Coding::ALL {
+        let q = quality(coding);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((coding, q));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// Text based formats benefit from compression, while most binary formats are already compressed.
+pub fn is_compressible(media_type: &MediaType) -> bool {
+    let ty = media_type.ty.as_str();
+    let subtype = media_type.subty.as_str();
+    let suffix = media_type.suffix.map(|s| s.as_str());
+    matches!(ty, "text")
+        || matches!(suffix, Some("json" | "xml"))
+        || matches!(
+            (ty, subtype),
+            (
+                "application",
+                "json" | "xml" | "javascript" | "ecmascript" | "wasm" | "yaml" | "toml"
+            ) | ("image", "svg")
+        )
+}
+
+/// Entity tags differ between the content codings of a representation.
+pub fn etag(etag: HeaderValue, coding: Option<Coding>) -> HeaderValue {
+    match coding {
+        None => etag,
+        Some(coding) => {
+            let opaque = etag.to_str().unwrap().trim_matches('"');
+            HeaderValue::from_str(&format!("\"{opaque}-{}\"", coding.token())).unwrap()
+        }
+    }
+}
+
+/// The encoded form of the value at `key`, using a pre-compressed variant if one is stored.
+pub fn encoded(
+    key: &Path,
+    data: &[u8],
+    coding: Coding,
+    db: Arc<impl StorageBackend>,
+) -> Result<Vec<u8>> {
+    match db.get(variant_key(key, data, coding))? {
+        Some(variant) => Ok(variant),
+        None => coding.encode(data),
+    }
+}
+
+/// Returns the storage operations which store a variant of the value at `key`
+/// for each content coding, if the media type is worth compressing.
+pub fn store_variants(
+    key: &Path,
+    data: &[u8],
+    media_type: &MediaType,
+) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+    if !is_compressible(media_type) {
+        return Ok(vec![]);
+    }
+    let mut updates = vec![];
+    for coding in Coding::ALL {
+        updates.push((variant_key(key, data, coding), Some(coding.encode(data)?)));
+    }
+    Ok(updates)
+}
+
+/// Returns the storage operations which remove every stored variant of the value at `key`.
+pub fn remove_variants(
+    key: &Path,
+    db: Arc<impl StorageBackend>,
+) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+    let keys = db.keys(variants_prefix(key))?;
+    Ok(keys.into_iter().map(|k| (k, None)).collect())
+}
+
+/// Returns the storage operations which copy the stored variants of the value at `source`
+/// to the value at `destination`.
+pub fn copy_variants(
+    source: &Path,
+    destination: &Path,
+    db: Arc<impl StorageBackend>,
+) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+    let source_prefix = variants_prefix(source);
+    let destination_prefix = variants_prefix(destination);
+    let mut updates = vec![];
+    for key in db.keys(&source_prefix)? {
+        let variant = &key.to_string_lossy()[source_prefix.len()..];
+        if let Some(value) = db.get(&key)? {
+            updates.push((
+                PathBuf::from(format!("{destination_prefix}{variant}")),
+                Some(value),
+            ));
+        }
+    }
+    Ok(updates)
+}
+
+fn variant_key(key: &Path, data: &[u8], coding: Coding) -> PathBuf {
+    PathBuf::from(format!(
+        "{}{:x}.{}",
+        variants_prefix(key),
+        md5::compute(data),
+        coding.token()
+    ))
+}
+
+/// Prefix of the keys of every variant of the value at `key`, but none of a key below it.
+fn variants_prefix(key: &Path) -> String {
+    format!(
+        "{NAMESPACE}{}{}",
+        key.to_string_lossy(),
+        crate::util::KEY_SEPARATOR
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept_encoding(value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(header::ACCEPT_ENCODING, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let text = MediaType::parse("text/plain").unwrap();
+        let json_ld = MediaType::parse("application/ld+json").unwrap();
+        let png = MediaType::parse("image/png").unwrap();
+
+        let headers = accept_encoding("gzip, deflate, br, zstd");
+        assert_eq!(negotiate(&headers, &text), Some(Coding::Brotli));
+        assert_eq!(negotiate(&headers, &json_ld), Some(Coding::Brotli));
+        assert_eq!(negotiate(&headers, &png), None);
+
+        let headers = accept_encoding("br;q=0.5, gzip;q=0.8");
+        assert_eq!(negotiate(&headers, &text), Some(Coding::Gzip));
+
+        let headers = accept_encoding("*, br;q=0");
+        assert_eq!(negotiate(&headers, &text), Some(Coding::Zstd));
+
+        let headers = accept_encoding("identity");
+        assert_eq!(negotiate(&headers, &text), None);
+        assert_eq!(negotiate(&HeaderMap::new(), &text), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"compressible compressible compressible".repeat(10);
+        for coding in Coding::ALL {
+            let encoded = coding.encode(&data).unwrap();
+            assert!(encoded.len() < data.len());
+            assert_eq!(
+                coding.decode(&encoded, data.len()).unwrap(),
+                Some(data.clone())
+            );
+            assert_eq!(coding.decode(&encoded, data.len() - 1).unwrap(), None);
+        }
+
+        let headers = HeaderMap::from_iter([(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static("gzip, br"),
+        )]);
+        let body = Coding::Brotli
+            .encode(&Coding::Gzip.encode(&data).unwrap())
+            .unwrap();
+        assert_eq!(
+            decode_body(&headers, body.clone(), 1024).unwrap(),
+            Ok(data.clone())
+        );
+        assert_eq!(
+            decode_body(&headers, body, 100).unwrap(),
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        assert!(decode_body(&headers, data.clone(), 1024).is_err());
+
+        let headers =
+            HeaderMap::from_iter([(header::CONTENT_ENCODING, HeaderValue::from_static("lzma"))]);
+        assert_eq!(
+            decode_body(&headers, data, 1024).unwrap(),
+            Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+    }
+
+    #[test]
+    fn test_variant_key() {
+        let variant = variant_key(Path::new("/docs/a.txt"), b"a", Coding::Gzip);
+        let nested = variant_key(Path::new("/docs/a.txt/b.txt"), b"b", Coding::Gzip);
+        let prefix = variants_prefix(Path::new("/docs/a.txt"));
+        assert!(variant.to_string_lossy().starts_with(&prefix));
+        assert!(!nested.to_string_lossy().starts_with(&prefix));
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Result, anyhow};
+use http::{HeaderMap, HeaderValue};
+
+use crate::content_negotiation::PathExtensions;
+use crate::encoding;
+use crate::storage::StorageBackend;
+use crate::util;
+
+/// Request header with the number of seconds until a stored representation expires.
+pub const TTL_HEADER: &str = "x-h2kv-ttl";
+/// Request and response header with the HTTP-date at which a stored representation expires.
+pub const EXPIRES_HEADER: &str = "x-h2kv-expires";
+
+/// Name of the extension attribute holding the expiry time.
+pub const ATTRIBUTE: &str = "expires";
+
+/// The expiry time requested in the headers of a write, if any.
+pub fn requested(headers: &HeaderMap) -> Result<Option<SystemTime>> {
+    match (headers.get(TTL_HEADER), headers.get(EXPIRES_HEADER)) {
+        (Some(ttl), _) => {
+            let seconds = ttl
+                .to_str()?
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| anyhow!("invalid {TTL_HEADER}: {e}"))?;
+            let expires = SystemTime::now()
+                .checked_add(Duration::from_secs(seconds))
+                .ok_or_else(|| anyhow!("invalid {TTL_HEADER}: {seconds} is out of range"))?;
+            Ok(Some(expires))
+        }
+        (None, Some(expires)) => {
+            let expires = httpdate::parse_http_date(expires.to_str()?)
+                .map_err(|e| anyhow!("invalid {EXPIRES_HEADER}: {e}"))?;
+            Ok(Some(expires))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// The extension attribute recording `expires`.
+pub fn attribute(expires: SystemTime) -> (String, serde_json::Value) {
+    (ATTRIBUTE.to_string(), util::micros(expires).into())
+}
+
+pub fn header_value(expires: SystemTime) -> HeaderValue {
+    HeaderValue::from_str(&httpdate::fmt_http_date(expires)).unwrap()
+}
+
+/// Delete every representation which has expired.
+pub fn sweep(db: Arc<impl StorageBackend>) -> Result<usize> {
+    let now = SystemTime::now();
+    let mut swept = 0;
+
+    for extensions_key in db.keys(PathExtensions::key_prefix("", Path::new("/")))? {
+        let Some(path) = PathExtensions::path_of("", &extensions_key) else {
+            continue;
+        };
+
+        let mut extensions = PathExtensions::get_for_path(&path, db.clone());
+        let expired: Vec<String> = extensions
+            .entries()
+            .filter(|(ext, _)| extensions.get_expiry(ext).is_some_and(|e| e <= now))
+            .map(|(ext, _)| ext.clone())
+            .collect();
+        if expired.is_empty() {
+            continue;
+        }
+
+        let mut updates = vec![];
+        for ext in expired.iter() {
+            let key = extensions.key_of(ext);
+            log::info!("expired {key:?}");
+            updates.extend(encoding::remove_variants(&key, db.clone())?);
+        }
+        updates.extend(extensions.remove_representations(&expired)?);
+        db.batch_update(updates)?;
+        swept += expired.len();
+    }
+
+    Ok(swept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requested() {
+        let mut headers = HeaderMap::new();
+        assert!(requested(&headers).unwrap().is_none());
+
+        headers.insert(
+            EXPIRES_HEADER,
+            HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"),
+        );
+        let expires = requested(&headers).unwrap().unwrap();
+        assert_eq!(header_value(expires), "Sun, 06 Nov 1994 08:49:37 GMT");
+
+        headers.insert(TTL_HEADER, HeaderValue::from_static("60"));
+        let expires = requested(&headers).unwrap().unwrap();
+        assert!(expires > SystemTime::now() + Duration::from_secs(59));
+
+        headers.insert(TTL_HEADER, HeaderValue::from_static("soon"));
+        assert!(requested(&headers).is_err());
+        headers.insert(TTL_HEADER, HeaderValue::from_static("18446744073709551615"));
+        assert!(requested(&headers).is_err());
+    }
+}
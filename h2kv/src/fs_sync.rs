@@ -16,10 +16,8 @@ pub fn collect_updates(updates_rx: &Receiver<PathBuf>) -> Vec<PathBuf> {
     updates.dedup();
     updates
         .into_iter()
-        .filter(|k| {
-            let ext = k.extension().unwrap().to_str().unwrap();
-            ext != PathExtensions::META_EXT
-        })
+        // keys outside of the user namespace are not files
+        .filter(|k| k.is_absolute())
         .collect()
 }
 
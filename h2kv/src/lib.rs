@@ -1,16 +1,50 @@
+pub mod migration;
 pub mod runtime;
 pub mod server;
 
 mod storage;
 pub use storage::{StorageBackend, StorageFactory};
 
+mod conditional;
 mod content_negotiation;
+mod encoding;
+mod expiry;
 mod fs_sync;
+mod metadata;
+mod methods;
+mod pointer;
+mod relocation;
+mod s3;
+mod trash;
+mod versioning;
+mod webdav;
 
 mod ignore_filter;
 pub use ignore_filter::IgnoreFilter;
 
+mod type_preferences;
+pub use type_preferences::TypePreferences;
+
+mod transcoding;
+pub use transcoding::Transcoding;
+
+mod schemas;
+pub use schemas::Schemas;
+
+mod auth;
+pub use auth::Tokens;
+
+mod presign;
+pub use presign::SigningKey;
+
+mod write_protection;
+pub use write_protection::WriteOnce;
+
+mod cors;
+pub use cors::Cors;
+
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -19,6 +53,21 @@ pub struct Config {
     pub sync_dir: Option<PathBuf>,
     pub sync_write: bool,
     pub sync_ignore: IgnoreFilter,
+    pub type_preferences: TypePreferences,
+    pub transcoding: Transcoding,
+    pub schemas: Schemas,
+    pub tokens: Tokens,
+    pub signing_key: Option<SigningKey>,
+    pub s3_endpoint: Option<PathBuf>,
+    pub read_only: bool,
+    pub write_once: WriteOnce,
+    pub cors: Cors,
+    pub keep_versions: Option<usize>,
+    pub trash_retention: Option<Duration>,
+    pub precompress: bool,
+    pub max_decoded_size: usize,
+    pub guess_types: bool,
+    pub sniff_types: bool,
     pub daemon: bool,
     pub pidfile: Option<PathBuf>,
     pub log_filename: Option<PathBuf>,
@@ -26,6 +75,7 @@ pub struct Config {
 
 mod util {
     use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     /// Returns `path` with all file extensions removed.
     pub fn path_stem(path: &Path) -> PathBuf {
@@ -41,6 +91,29 @@ mod util {
         }
     }
 
+    /// Separates a storage key from the names of values kept for it in an internal namespace.
+    /// It can not occur in user paths, which are taken from URIs and file names.
+    pub const KEY_SEPARATOR: char = '\0';
+
+    /// Timestamp used in stored metadata.
+    pub fn micros(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64
+    }
+
+    pub fn from_micros(micros: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_micros(micros)
+    }
+
+    /// Returns `text` with the characters which are markup in XML replaced by entities.
+    pub fn xml_escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
     #[cfg(test)]
     mod tests {
         use super::*;
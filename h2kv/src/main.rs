@@ -1,5 +1,6 @@
 use std::path::PathBuf;
 use std::sync::{Arc, mpsc};
+use std::time::Duration;
 
 use anyhow::{Result, anyhow, bail};
 use auto_args::AutoArgs;
@@ -17,6 +18,32 @@ struct Opt {
     sync_dir: Option<PathBuf>,
     /// write to the synchronized directory on exit and SIGHUP
     sync_write: bool,
+    /// require bearer tokens with the digests and scopes defined in this file
+    tokens_file: Option<PathBuf>,
+    /// accept URLs pre-signed with the secret in this file, see `h2kv presign --help`
+    signing_key_file: Option<PathBuf>,
+    /// serve a subset of the S3 API below this path, with buckets mapped to key prefixes
+    s3_endpoint: Option<PathBuf>,
+    /// reject requests which write to the database with 405 Method Not Allowed
+    read_only: bool,
+    /// allow browser apps of these origins to make requests, or '*' for any origin
+    cors_origins: Option<String>,
+    /// methods allowed to other origins, default: GET, HEAD, PUT, DELETE
+    cors_methods: Option<String>,
+    /// request headers allowed to other origins, default: authorization, content-type, if-match, if-none-match
+    cors_headers: Option<String>,
+    /// enable object versioning, keeping up to N previous versions of each object
+    keep_versions: Option<usize>,
+    /// enable soft delete, keeping deleted objects in the trash for N seconds
+    trash_retention: Option<u64>,
+    /// store compressed variants of text objects when they are written
+    precompress: bool,
+    /// largest request body accepted after decoding its content-encoding, in bytes, default: 67108864
+    max_decoded_size: Option<usize>,
+    /// store objects written without a content-type as application/octet-stream
+    no_type_guessing: bool,
+    /// detect the media type of objects without a content-type or known extension from their contents
+    sniff_types: bool,
     /// fork into background process
     daemon: bool,
     /// PID file, ignored unless --daemon is set
@@ -25,6 +52,19 @@ struct Opt {
     log_filename: Option<PathBuf>,
 }
 
+/// Mint a URL granting a single method on its key until it expires.
+#[derive(Debug, AutoArgs)]
+struct PresignOpt {
+    /// file with the secret the server was started with as --signing-key-file
+    signing_key_file: PathBuf,
+    /// method allowed by the URL, default: GET
+    method: Option<String>,
+    /// seconds until the URL expires, default: 3600
+    expires_in: Option<u64>,
+    /// URL of the key, such as https://localhost:5928/shared/report.pdf
+    url: String,
+}
+
 impl TryFrom<Opt> for h2kv::Config {
     type Error = anyhow::Error;
 
@@ -53,6 +93,19 @@ impl TryFrom<Opt> for h2kv::Config {
             bail!("no sync-dir specified for sync-write");
         }
 
+        match value.s3_endpoint {
+            Some(ref endpoint) if !endpoint.is_absolute() => {
+                bail!("s3-endpoint {endpoint:?} is not an absolute path");
+            }
+            _ => (),
+        }
+
+        if (value.cors_methods.is_some() || value.cors_headers.is_some())
+            && value.cors_origins.is_none()
+        {
+            log::warn!("'--cors-methods' and '--cors-headers' ignored without '--cors-origins'");
+        }
+
         if value.pidfile.is_some() && !value.daemon {
             log::warn!(
                 "'--pidfile {:?}' ignored because '--daemon' is not set",
@@ -68,6 +121,26 @@ impl TryFrom<Opt> for h2kv::Config {
         }
 
         let sync_ignore = h2kv::IgnoreFilter::try_from_env()?;
+        let type_preferences = h2kv::TypePreferences::try_from_env()?;
+        let transcoding = h2kv::Transcoding::try_from_env()?;
+        let schemas = h2kv::Schemas::try_from_env()?;
+        let write_once = h2kv::WriteOnce::try_from_env()?;
+        let tokens = match value.tokens_file {
+            Some(ref tokens_file) => h2kv::Tokens::try_from_file(tokens_file)?,
+            None => h2kv::Tokens::default(),
+        };
+        let cors = match value.cors_origins {
+            Some(ref origins) => h2kv::Cors::try_new(
+                origins,
+                value.cors_methods.as_deref(),
+                value.cors_headers.as_deref(),
+            )?,
+            None => h2kv::Cors::default(),
+        };
+        let signing_key = match value.signing_key_file {
+            Some(ref signing_key_file) => Some(h2kv::SigningKey::try_from_file(signing_key_file)?),
+            None => None,
+        };
 
         Ok(Self {
             port: value.port.unwrap_or(5928),
@@ -75,6 +148,21 @@ impl TryFrom<Opt> for h2kv::Config {
             sync_dir: value.sync_dir,
             sync_write: value.sync_write,
             sync_ignore,
+            type_preferences,
+            transcoding,
+            schemas,
+            tokens,
+            signing_key,
+            s3_endpoint: value.s3_endpoint,
+            read_only: value.read_only,
+            write_once,
+            cors,
+            keep_versions: value.keep_versions,
+            trash_retention: value.trash_retention.map(Duration::from_secs),
+            precompress: value.precompress,
+            max_decoded_size: value.max_decoded_size.unwrap_or(64 * 1024 * 1024),
+            guess_types: !value.no_type_guessing,
+            sniff_types: value.sniff_types,
             daemon: value.daemon,
             pidfile: value.pidfile,
             log_filename: value.log_filename,
@@ -91,15 +179,17 @@ fn main() -> Result<()> {
         env_logger::try_init()?;
     }
 
+    presign_intercept()?;
     help_intercept();
-    let config: h2kv::Config = Opt::from_args().try_into()?;
+    let config: Arc<h2kv::Config> = Arc::new(Opt::from_args().try_into()?);
 
     let (updates_tx, updates_rx) = mpsc::channel::<PathBuf>();
 
     let storage_dir = config.storage_dir.clone();
+    let port = config.port;
     let updates_tx_clone = updates_tx.clone();
     let lock_resources = move || -> Result<_, anyhow::Error> {
-        let listener = std::net::TcpListener::bind(format!("127.0.0.1:{}", config.port))?;
+        let listener = std::net::TcpListener::bind(format!("127.0.0.1:{port}"))?;
         let db = h2kv::StorageFactory::try_create(&storage_dir, updates_tx_clone)?;
         Ok((listener, Arc::new(db)))
     };
@@ -119,6 +209,8 @@ fn main() -> Result<()> {
         lock_resources().map_err(|e| anyhow!("resource lock failure: {e}"))?
     };
 
+    h2kv::migration::migrate(db.clone())?;
+
     if config.sync_ignore.is_active() {
         log::warn!("ignore filter {}", config.sync_ignore);
     }
@@ -141,6 +233,7 @@ fn main() -> Result<()> {
             let listener = tokio::net::TcpListener::bind(addr).await?;
 
             files.do_read(db.clone())?;
+            h2kv::runtime::spawn_maintenance(db.clone(), &config);
 
             loop {
                 tokio::select! {
@@ -161,7 +254,7 @@ fn main() -> Result<()> {
                         files.do_write(db.clone())?;
                         files.do_read(db.clone())?;
                     }
-                    _ = h2kv::server::listen(&listener, db.clone()) => {},
+                    _ = h2kv::server::listen(&listener, db.clone(), config.clone()) => {},
                 }
             }
 
@@ -176,6 +269,33 @@ async fn signal(kind: SignalKind) -> std::io::Result<()> {
     Ok(())
 }
 
+/// Handle `h2kv presign ...`, which prints a pre-signed URL and exits.
+fn presign_intercept() -> Result<()> {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) != Some("presign") {
+        return Ok(());
+    }
+    if args.contains(&"--help".to_string()) {
+        eprintln!("{}", PresignOpt::help());
+        std::process::exit(1);
+    }
+    let opt =
+        PresignOpt::from_iter(&args[1..]).map_err(|e| anyhow!("{e}\n{}", PresignOpt::usage()))?;
+
+    let key = h2kv::SigningKey::try_from_file(&opt.signing_key_file)?;
+    let method = http::Method::from_bytes(
+        opt.method
+            .unwrap_or("GET".to_string())
+            .to_ascii_uppercase()
+            .as_bytes(),
+    )?;
+    let expires =
+        std::time::SystemTime::now() + Duration::from_secs(opt.expires_in.unwrap_or(3600));
+    let url: http::Uri = opt.url.parse()?;
+    println!("{}", key.presign(&url, &method, expires)?);
+    std::process::exit(0);
+}
+
 fn help_intercept() {
     let args = std::env::args().collect::<Vec<_>>();
     if args.contains(&"--help".to_string()) {
@@ -189,9 +309,36 @@ fn help_intercept() {
             h2kv::IgnoreFilter::ENV_NAME,
             h2kv::IgnoreFilter::ENV_DESCRIPTION
         );
+        let type_preferences_description = format!(
+            "{}: {}",
+            h2kv::TypePreferences::ENV_NAME,
+            h2kv::TypePreferences::ENV_DESCRIPTION
+        );
+        let transcoding_description = format!(
+            "{}: {}",
+            h2kv::Transcoding::ENV_NAME,
+            h2kv::Transcoding::ENV_DESCRIPTION
+        );
+        let schemas_description = format!(
+            "{}: {}",
+            h2kv::Schemas::ENV_NAME,
+            h2kv::Schemas::ENV_DESCRIPTION
+        );
+        let write_once_description = format!(
+            "{}: {}",
+            h2kv::WriteOnce::ENV_NAME,
+            h2kv::WriteOnce::ENV_DESCRIPTION
+        );
+        let tokens_description = format!("TOKENS FILE: {}", h2kv::Tokens::FILE_DESCRIPTION);
         msg_lines.append(&mut vec![
             "Environment Variables:",
             &ignore_filter_description,
+            &type_preferences_description,
+            &transcoding_description,
+            &schemas_description,
+            &write_once_description,
+            "",
+            &tokens_description,
         ]);
         eprintln!("{}", msg_lines.join("\n"));
 
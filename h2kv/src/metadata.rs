@@ -0,0 +1,79 @@
+use http::{HeaderMap, HeaderName, HeaderValue, header};
+
+/// Request headers starting with this prefix are stored with a representation.
+pub const HEADER_PREFIX: &str = "x-h2kv-meta-";
+
+/// Standard representation headers which are stored along with the prefixed headers.
+const STORED_HEADERS: [HeaderName; 3] = [
+    header::CACHE_CONTROL,
+    header::CONTENT_DISPOSITION,
+    header::CONTENT_LANGUAGE,
+];
+
+/// Name of the extension attribute holding the stored headers.
+pub const ATTRIBUTE: &str = "headers";
+
+/// The extension attribute recording the metadata headers of a write, if there are any.
+pub fn attribute(headers: &HeaderMap) -> Option<(String, serde_json::Value)> {
+    let mut stored = serde_json::Map::new();
+    for name in headers.keys() {
+        if !(name.as_str().starts_with(HEADER_PREFIX) || STORED_HEADERS.contains(name)) {
+            continue;
+        }
+        let values: Vec<&str> = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect();
+        if !values.is_empty() {
+            stored.insert(name.to_string(), values.join(", ").into());
+        }
+    }
+
+    match stored.is_empty() {
+        true => None,
+        false => Some((ATTRIBUTE.to_string(), serde_json::Value::Object(stored))),
+    }
+}
+
+/// Add the stored metadata headers to the headers of a response.
+pub fn replay(attribute: Option<&serde_json::Value>, headers: &mut HeaderMap) {
+    let Some(serde_json::Value::Object(stored)) = attribute else {
+        return;
+    };
+    for (name, value) in stored {
+        if let (Ok(name), Some(Ok(value))) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            value.as_str().map(HeaderValue::from_str),
+        ) {
+            headers.append(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attribute_replay() {
+        let mut request = HeaderMap::new();
+        request.append("x-h2kv-meta-author", HeaderValue::from_static("someone"));
+        request.append("x-h2kv-meta-tag", HeaderValue::from_static("a"));
+        request.append("x-h2kv-meta-tag", HeaderValue::from_static("b"));
+        request.append(header::CONTENT_LANGUAGE, HeaderValue::from_static("fr"));
+        request.append(header::USER_AGENT, HeaderValue::from_static("curl"));
+
+        let (name, stored) = attribute(&request).unwrap();
+        assert_eq!(name, ATTRIBUTE);
+
+        let mut response = HeaderMap::new();
+        replay(Some(&stored), &mut response);
+        assert_eq!(response.len(), 3);
+        assert_eq!(response["x-h2kv-meta-author"], "someone");
+        assert_eq!(response["x-h2kv-meta-tag"], "a, b");
+        assert_eq!(response[header::CONTENT_LANGUAGE], "fr");
+
+        assert!(attribute(&HeaderMap::new()).is_none());
+    }
+}
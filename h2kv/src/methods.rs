@@ -0,0 +1,169 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+
+use crate::Config;
+use crate::content_negotiation::PathExtensions;
+use crate::storage::StorageBackend;
+use crate::webdav;
+
+/// Methods which do not change the stored data.
+const READ_METHODS: [&str; 4] = ["GET", "HEAD", "OPTIONS", "PROPFIND"];
+
+/// Methods which change the stored data of a resource or a collection.
+const WRITE_METHODS: [&str; 5] = ["PUT", "DELETE", "COPY", "MOVE", "MKCOL"];
+
+/// Methods which are recognized, but may not be allowed on every resource.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-9.1
+const KNOWN_METHODS: [&str; 13] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH", "PROPFIND",
+    "MKCOL", "COPY", "MOVE",
+];
+
+/// Whether `method` is recognized, so that a request with it is refused with 405 rather than 501.
+pub fn is_known(method: &Method) -> bool {
+    KNOWN_METHODS.contains(&method.as_str())
+}
+
+/// Whether a representation is stored at `path`, or any representation of its resource
+/// if `path` has no extension.
+pub fn exists(path: &Path, db: Arc<impl StorageBackend>) -> Result<bool> {
+    let extensions = PathExtensions::get_for_path(path, db);
+    if let Some(extension) = extensions.extension_of(path) {
+        return Ok(extensions.get_media_type(&extension)?.is_some());
+    }
+    for (extension, _) in extensions.entries() {
+        if extensions.get_media_type(extension)?.is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Methods allowed on the resource at `path`, or on the server as a whole if it is `None`.
+/// Whether the resource `exists` matters to write-once resources.
+pub fn allowed(path: Option<&Path>, exists: bool, config: &Config) -> Vec<&'static str> {
+    let mut allowed = Vec::from(READ_METHODS);
+    if config.read_only {
+        return allowed;
+    }
+    allowed.extend(WRITE_METHODS);
+    // write-once resources can be created, but not replaced nor removed
+    if path.is_some_and(|path| config.write_once.protects(path)) {
+        allowed.retain(|m| !matches!(*m, "DELETE" | "MOVE"));
+        if exists {
+            allowed.retain(|m| *m != "PUT");
+        }
+    }
+    // S3 multipart uploads are made with POST, and so are restores of trashed resources
+    // with the "restore" query, which names a distinct target
+    let post = match path {
+        None => config.trash_retention.is_some() || config.s3_endpoint.is_some(),
+        Some(path) => config
+            .s3_endpoint
+            .as_ref()
+            .is_some_and(|e| path.starts_with(e)),
+    };
+    if post {
+        allowed.push("POST");
+    }
+    allowed
+}
+
+/// Headers of the response to OPTIONS, with the allowed methods and the capabilities of the server.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-9.3.7
+pub fn options(path: Option<&Path>, exists: bool, config: &Config) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.append(header::ALLOW, allow_header(path, exists, config));
+    headers.append(webdav::DAV_HEADER, HeaderValue::from_static("1"));
+    headers.append(header::CONTENT_LENGTH, HeaderValue::from_static("0"));
+    headers
+}
+
+/// Status and headers of the response refusing a known method which is not allowed on `path`.
+/// https://www.rfc-editor.org/rfc/rfc9110.html#section-15.5.6
+pub fn method_not_allowed(path: &Path, exists: bool, config: &Config) -> (StatusCode, HeaderMap) {
+    let headers = HeaderMap::from_iter([(header::ALLOW, allow_header(Some(path), exists, config))]);
+    (StatusCode::METHOD_NOT_ALLOWED, headers)
+}
+
+fn allow_header(path: Option<&Path>, exists: bool, config: &Config) -> HeaderValue {
+    HeaderValue::from_str(&allowed(path, exists, config).join(", ")).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::WriteOnce;
+
+    fn config() -> Config {
+        Config {
+            port: 5928,
+            storage_dir: PathBuf::from("/tmp"),
+            sync_dir: None,
+            sync_write: false,
+            sync_ignore: crate::IgnoreFilter::try_from_str("").unwrap(),
+            type_preferences: Default::default(),
+            transcoding: Default::default(),
+            schemas: Default::default(),
+            tokens: Default::default(),
+            signing_key: None,
+            s3_endpoint: Some(PathBuf::from("/s3")),
+            read_only: false,
+            write_once: WriteOnce::try_from_str("/archive/").unwrap(),
+            cors: Default::default(),
+            keep_versions: None,
+            trash_retention: None,
+            precompress: false,
+            max_decoded_size: 1024,
+            guess_types: true,
+            sniff_types: false,
+            daemon: false,
+            pidfile: None,
+            log_filename: None,
+        }
+    }
+
+    #[test]
+    fn test_allowed() {
+        let config = config();
+        assert_eq!(
+            allowed(Some(Path::new("/docs/a.txt")), true, &config),
+            [
+                "GET", "HEAD", "OPTIONS", "PROPFIND", "PUT", "DELETE", "COPY", "MOVE", "MKCOL"
+            ]
+        );
+        assert_eq!(
+            allowed(Some(Path::new("/archive/a.txt")), false, &config),
+            ["GET", "HEAD", "OPTIONS", "PROPFIND", "PUT", "COPY", "MKCOL"]
+        );
+        assert_eq!(
+            allowed(Some(Path::new("/archive/a.txt")), true, &config),
+            ["GET", "HEAD", "OPTIONS", "PROPFIND", "COPY", "MKCOL"]
+        );
+        assert!(allowed(Some(Path::new("/s3/bucket/a")), false, &config).contains(&"POST"));
+        assert!(allowed(None, false, &config).contains(&"POST"));
+        let trash = Config {
+            s3_endpoint: None,
+            trash_retention: Some(std::time::Duration::from_secs(60)),
+            ..config.clone()
+        };
+        assert!(!allowed(Some(Path::new("/docs/a.txt")), true, &trash).contains(&"POST"));
+        assert!(allowed(None, false, &trash).contains(&"POST"));
+
+        let read_only = Config {
+            read_only: true,
+            ..config
+        };
+        assert_eq!(
+            allowed(None, false, &read_only),
+            ["GET", "HEAD", "OPTIONS", "PROPFIND"]
+        );
+        assert!(is_known(&Method::PATCH));
+        assert!(!is_known(&Method::from_bytes(b"BREW").unwrap()));
+    }
+}
@@ -0,0 +1,156 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::content_negotiation::PathExtensions;
+use crate::storage::StorageBackend;
+use crate::trash;
+
+/// Storage keys of users always start with "/", so this key can not be addressed.
+/// Holds the version of the layout of keys in the database, which is 1 if it is missing.
+pub const LAYOUT_KEY: &str = "layout";
+
+/// 1. Extensions stored at "<stem>.ext", among the representations of users.
+/// 2. Extensions stored in their own namespace.
+pub const LAYOUT_VERSION: u64 = 2;
+
+/// Bring a database created by a previous version up to date with the current layout of keys.
+/// Returns the version of the layout that was migrated from.
+pub fn migrate(db: Arc<impl StorageBackend>) -> Result<u64> {
+    let version = match db.get(LAYOUT_KEY)? {
+        None => 1,
+        Some(version) => String::from_utf8(version)?
+            .parse()
+            .map_err(|e| anyhow!("unparsed layout version: {e}"))?,
+    };
+    if version > LAYOUT_VERSION {
+        bail!("database layout version {version} is newer than supported ({LAYOUT_VERSION})");
+    }
+    if version == LAYOUT_VERSION {
+        return Ok(version);
+    }
+
+    let mut updates = vec![];
+    if version < 2 {
+        updates.extend(extensions_namespace(db.clone())?);
+    }
+    let moved = updates.len() / 2;
+    updates.push((
+        PathBuf::from(LAYOUT_KEY),
+        Some(LAYOUT_VERSION.to_string().into_bytes()),
+    ));
+    db.batch_update(updates)?;
+
+    log::info!(
+        "migrated database layout from version {version} to {LAYOUT_VERSION} ({moved} keys)"
+    );
+    Ok(version)
+}
+
+/// Returns the storage operations which move the extensions out of the user namespace,
+/// and out of the part of the trash which mirrors it.
+fn extensions_namespace(db: Arc<impl StorageBackend>) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+    let mut updates = vec![];
+    for namespace in ["", trash::NAMESPACE] {
+        for key in db.keys(format!("{namespace}/"))? {
+            if key.extension().and_then(|e| e.to_str()) != Some("ext") {
+                continue;
+            }
+            // users could write keys with this extension too, which are kept unless they hold
+            // a map of extensions to media types, and so can not be told apart from metadata
+            let Some(value) = db.get(&key)?.filter(|value| is_extensions_map(value)) else {
+                continue;
+            };
+            let stem = key.to_string_lossy()[namespace.len()..].to_string();
+            let path = Path::new(&stem).with_extension("");
+            updates.push((key, None));
+            updates.push((PathExtensions::key_prefix(namespace, &path), Some(value)));
+        }
+    }
+    Ok(updates)
+}
+
+/// Whether `value` is a JSON object of which each entry is a media type,
+/// or a map of attributes with a media type as its "type".
+fn is_extensions_map(value: &[u8]) -> bool {
+    let Ok(map) = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(value)
+    else {
+        return false;
+    };
+    map.values().all(|entry| {
+        let media_type = match entry {
+            serde_json::Value::Object(attributes) => attributes.get("type"),
+            media_type => Some(media_type),
+        };
+        media_type
+            .and_then(|mt| mt.as_str())
+            .is_some_and(|mt| mediatype::MediaType::parse(mt).is_ok())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::StorageFactory;
+
+    #[test]
+    fn test_migrate() {
+        let storage_dir =
+            std::env::temp_dir().join(format!("h2kv-test-migrate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&storage_dir);
+        std::fs::create_dir_all(&storage_dir).unwrap();
+        let (updates_tx, _updates_rx) = mpsc::channel();
+        let db = Arc::new(StorageFactory::try_create(&storage_dir, updates_tx).unwrap());
+
+        // layout 1
+        let metadata: &[u8] = br#"{"txt": "text/plain"}"#;
+        let trashed: &[u8] = br#"{"txt": {"type": "text/plain", "deleted": 1}}"#;
+        db.put("/docs/about.ext", metadata).unwrap();
+        db.put("/docs/about.txt", b"about").unwrap();
+        db.put("/foo.ext", b"user data").unwrap();
+        db.put("trash/old.ext", trashed).unwrap();
+
+        assert_eq!(migrate(db.clone()).unwrap(), 1);
+        assert_eq!(db.get(LAYOUT_KEY).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.get("/docs/about.ext").unwrap(), None);
+        assert_eq!(
+            db.get(PathExtensions::storage_key(Path::new("/docs/about")))
+                .unwrap(),
+            Some(metadata.to_vec())
+        );
+        assert_eq!(db.get("/docs/about.txt").unwrap(), Some(b"about".to_vec()));
+        assert_eq!(
+            db.get(PathExtensions::key_prefix("trash", Path::new("/old")))
+                .unwrap(),
+            Some(trashed.to_vec())
+        );
+        // a user key which is not metadata is kept
+        assert_eq!(db.get("/foo.ext").unwrap(), Some(b"user data".to_vec()));
+        assert_eq!(
+            db.get(PathExtensions::storage_key(Path::new("/foo")))
+                .unwrap(),
+            None
+        );
+
+        // once migrated, nothing is moved again
+        db.put("/bar.ext", metadata).unwrap();
+        assert_eq!(migrate(db.clone()).unwrap(), LAYOUT_VERSION);
+        assert_eq!(db.get("/bar.ext").unwrap(), Some(metadata.to_vec()));
+
+        std::fs::remove_dir_all(&storage_dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_extensions_map() {
+        assert!(is_extensions_map(br#"{"txt": "text/plain"}"#));
+        assert!(is_extensions_map(
+            br#"{"en.html": {"type": "text/html", "language": "en"}}"#
+        ));
+        assert!(!is_extensions_map(b"user data"));
+        assert!(!is_extensions_map(br#"{"name": "not a media type"}"#));
+        assert!(!is_extensions_map(br#"["txt"]"#));
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::Result;
+use mediatype::MediaType;
+
+use crate::transcoding::Format;
+
+/// Query parameter holding a JSON Pointer into the requested representation.
+pub const QUERY_PARAM: &str = "pointer";
+
+/// The JSON Pointer requested with the *pointer* query parameter, if any.
+pub fn requested(query: &[(String, String)]) -> Option<&str> {
+    query
+        .iter()
+        .find(|(k, _)| k == QUERY_PARAM)
+        .map(|(_, pointer)| pointer.as_str())
+}
+
+/// A pointer is either empty, referring to the whole document, or a list of "/"-prefixed tokens.
+/// https://www.rfc-editor.org/rfc/rfc6901.html#section-3
+pub fn is_valid(pointer: &str) -> bool {
+    pointer.is_empty() || pointer.starts_with('/')
+}
+
+/// The value referenced by `pointer` in structured `data`, encoded in the same format.
+/// If the media type is not a structured data format, or the pointer does not resolve,
+/// `Ok(None)` is returned.
+pub fn resolve(media_type: &MediaType, data: &[u8], pointer: &str) -> Result<Option<Vec<u8>>> {
+    let Some(format) = Format::from_media_type(media_type) else {
+        return Ok(None);
+    };
+    match format.decode(data)?.pointer(pointer) {
+        Some(fragment) => Ok(Some(format.encode(fragment)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let json = MediaType::parse("application/json").unwrap();
+        let data = br#"{"servers":[{"name":"a/b","port":5928}],"~tilde":true}"#;
+
+        let at = |pointer| resolve(&json, data, pointer).unwrap();
+        assert_eq!(at("/servers/0/port"), Some(b"5928".to_vec()));
+        assert_eq!(at("/servers/0/name"), Some(br#""a/b""#.to_vec()));
+        assert_eq!(at("/~0tilde"), Some(b"true".to_vec()));
+        assert_eq!(at(""), Some(data.to_vec()));
+        assert_eq!(at("/servers/1"), None);
+        assert_eq!(at("/missing"), None);
+
+        let text = MediaType::parse("text/plain").unwrap();
+        assert_eq!(resolve(&text, b"{}", "").unwrap(), None);
+
+        assert!(is_valid("/a/b"));
+        assert!(!is_valid("a/b"));
+    }
+}
@@ -0,0 +1,264 @@
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow, bail};
+use http::{Method, Uri};
+use ring::hmac;
+
+/// Query parameter with the time a pre-signed URL expires, in seconds since the unix epoch.
+pub const EXPIRES_PARAM: &str = "x-h2kv-expires";
+
+/// Query parameter with the method a pre-signed URL allows. GET also allows HEAD.
+pub const METHOD_PARAM: &str = "x-h2kv-method";
+
+/// Query parameter with the HMAC-SHA256 signature of a pre-signed URL, in hex.
+pub const SIGNATURE_PARAM: &str = "x-h2kv-signature";
+
+/// Server secret used to sign URLs granting time-limited access to a single key.
+/// The signature covers the method, key and every query parameter, including the expiry,
+/// so nothing is stored to validate it and no parameter can be added to the URL.
+#[derive(Clone)]
+pub struct SigningKey {
+    key: hmac::Key,
+}
+
+/// Outcome of checking the pre-signed URL parameters of a request.
+#[derive(Debug, PartialEq)]
+pub enum Presigned {
+    /// The request does not carry a signature.
+    Unsigned,
+    /// The signature is valid for the request and has not expired.
+    Valid,
+    /// The signature is malformed, expired, or does not match the request.
+    Invalid(&'static str),
+}
+
+impl SigningKey {
+    pub fn try_from_file(path: &Path) -> Result<Self> {
+        let secret = std::fs::read(path)
+            .map_err(|e| anyhow!("unreadable signing key file {path:?}: {e}"))?;
+        Self::try_from_secret(secret.trim_ascii())
+    }
+
+    pub fn try_from_secret(secret: &[u8]) -> Result<Self> {
+        if secret.len() < 16 {
+            bail!("signing key is shorter than 16 bytes");
+        }
+        Ok(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+        })
+    }
+
+    /// Returns `uri` with the query parameters allowing `method` on its path until `expires`.
+    pub fn presign(&self, uri: &Uri, method: &Method, expires: SystemTime) -> Result<Uri> {
+        let expires = expires.duration_since(UNIX_EPOCH)?.as_secs().to_string();
+        let mut query: Vec<(String, String)> =
+            form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect();
+        query.push((EXPIRES_PARAM.to_string(), expires.clone()));
+        query.push((METHOD_PARAM.to_string(), method.to_string()));
+        let signature = self.signature(method, Path::new(uri.path()), &query);
+        let params = form_urlencoded::Serializer::new(String::new())
+            .append_pair(EXPIRES_PARAM, &expires)
+            .append_pair(METHOD_PARAM, method.as_str())
+            .append_pair(SIGNATURE_PARAM, &signature)
+            .finish();
+        let path_and_query = match uri.query() {
+            Some(query) if !query.is_empty() => format!("{}?{query}&{params}", uri.path()),
+            _ => format!("{}?{params}", uri.path()),
+        };
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query.parse()?);
+        Ok(Uri::from_parts(parts)?)
+    }
+
+    /// Check the pre-signed URL parameters in `query` for a request of `method` on `path`.
+    pub fn check(&self, method: &Method, path: &Path, query: &[(String, String)]) -> Presigned {
+        self.check_at(method, path, query, SystemTime::now())
+    }
+
+    fn check_at(
+        &self,
+        method: &Method,
+        path: &Path,
+        query: &[(String, String)],
+        now: SystemTime,
+    ) -> Presigned {
+        let param = |name: &str| query.iter().find(|(k, _)| k == name).map(|(_, v)| v);
+        let Some(signature) = param(SIGNATURE_PARAM) else {
+            return Presigned::Unsigned;
+        };
+        let (Some(expires), Some(allowed)) = (param(EXPIRES_PARAM), param(METHOD_PARAM)) else {
+            return Presigned::Invalid("incomplete signature parameters");
+        };
+        let (Ok(expires), Ok(allowed)) = (
+            expires.parse::<u64>(),
+            Method::from_bytes(allowed.as_bytes()),
+        ) else {
+            return Presigned::Invalid("malformed signature parameters");
+        };
+        let Some(signature) = hex_decode(signature) else {
+            return Presigned::Invalid("malformed signature");
+        };
+
+        let signed = message(&allowed, path, query);
+        if hmac::verify(&self.key, &signed, &signature).is_err() {
+            return Presigned::Invalid("signature mismatch");
+        }
+        if now > UNIX_EPOCH + Duration::from_secs(expires) {
+            return Presigned::Invalid("signature expired");
+        }
+        if *method != allowed && !(*method == Method::HEAD && allowed == Method::GET) {
+            return Presigned::Invalid("method not signed");
+        }
+        Presigned::Valid
+    }
+
+    fn signature(&self, method: &Method, path: &Path, query: &[(String, String)]) -> String {
+        let tag = hmac::sign(&self.key, &message(method, path, query));
+        tag.as_ref().iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SigningKey(..)")
+    }
+}
+
+/// The signed message, which binds the method, key and query of a URL.
+/// The query is canonical: its parameters other than the signature are sorted and encoded.
+fn message(method: &Method, path: &Path, query: &[(String, String)]) -> Vec<u8> {
+    let mut params: Vec<&(String, String)> =
+        query.iter().filter(|(k, _)| k != SIGNATURE_PARAM).collect();
+    params.sort();
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params.into_iter().map(|(k, v)| (k, v)))
+        .finish();
+    format!("{method}\n{}\n{query}", path.to_string_lossy()).into_bytes()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let key = SigningKey::try_from_secret(b"0123456789abcdef").unwrap();
+        let expires = UNIX_EPOCH + Duration::from_secs(2_000_000_000);
+        let uri: Uri = "https://localhost:5928/shared/a.txt?version=2"
+            .parse()
+            .unwrap();
+        let presigned = key.presign(&uri, &Method::GET, expires).unwrap();
+        assert!(
+            presigned
+                .query()
+                .unwrap()
+                .starts_with("version=2&x-h2kv-expires=2000000000&")
+        );
+
+        let query: Vec<(String, String)> =
+            form_urlencoded::parse(presigned.query().unwrap().as_bytes())
+                .into_owned()
+                .collect();
+        let path = Path::new("/shared/a.txt");
+        let before = expires - Duration::from_secs(1);
+        let after = expires + Duration::from_secs(1);
+        assert_eq!(
+            key.check_at(&Method::GET, path, &query, before),
+            Presigned::Valid
+        );
+        assert_eq!(
+            key.check_at(&Method::HEAD, path, &query, before),
+            Presigned::Valid
+        );
+        assert!(matches!(
+            key.check_at(&Method::PUT, path, &query, before),
+            Presigned::Invalid(_)
+        ));
+        assert!(matches!(
+            key.check_at(&Method::GET, Path::new("/shared/b.txt"), &query, before),
+            Presigned::Invalid(_)
+        ));
+        assert!(matches!(
+            key.check_at(&Method::GET, path, &query, after),
+            Presigned::Invalid(_)
+        ));
+
+        let other = SigningKey::try_from_secret(b"fedcba9876543210").unwrap();
+        assert!(matches!(
+            other.check_at(&Method::GET, path, &query, before),
+            Presigned::Invalid(_)
+        ));
+        assert_eq!(key.check(&Method::GET, path, &[]), Presigned::Unsigned);
+        assert!(SigningKey::try_from_secret(b"short").is_err());
+    }
+
+    #[test]
+    fn test_query_is_signed() {
+        let key = SigningKey::try_from_secret(b"0123456789abcdef").unwrap();
+        let expires = UNIX_EPOCH + Duration::from_secs(2_000_000_000);
+        let before = expires - Duration::from_secs(1);
+        let path = Path::new("/shared/a.txt");
+        let signed_query = |uri: &str, method: &Method| -> Vec<(String, String)> {
+            let presigned = key.presign(&uri.parse().unwrap(), method, expires).unwrap();
+            form_urlencoded::parse(presigned.query().unwrap().as_bytes())
+                .into_owned()
+                .collect()
+        };
+        let with = |query: &[(String, String)], name: &str| {
+            let mut query = query.to_vec();
+            query.push((name.to_string(), String::new()));
+            query
+        };
+
+        // a parameter which was not signed, such as one widening a DELETE, is rejected
+        let delete = signed_query("/shared/a.txt", &Method::DELETE);
+        assert_eq!(
+            key.check_at(&Method::DELETE, path, &delete, before),
+            Presigned::Valid
+        );
+        for added in ["recursive", "all"] {
+            assert!(matches!(
+                key.check_at(&Method::DELETE, path, &with(&delete, added), before),
+                Presigned::Invalid(_)
+            ));
+        }
+        let get = signed_query("/shared/a.txt", &Method::GET);
+        for added in ["versions", "trash", "pointer"] {
+            assert!(matches!(
+                key.check_at(&Method::GET, path, &with(&get, added), before),
+                Presigned::Invalid(_)
+            ));
+        }
+
+        // a signed parameter can not be removed, but the order of parameters does not matter
+        let versioned = signed_query("/shared/a.txt?version=2", &Method::GET);
+        let removed: Vec<(String, String)> = versioned
+            .iter()
+            .filter(|(k, _)| k != "version")
+            .cloned()
+            .collect();
+        assert!(matches!(
+            key.check_at(&Method::GET, path, &removed, before),
+            Presigned::Invalid(_)
+        ));
+        let reordered: Vec<(String, String)> = versioned.iter().rev().cloned().collect();
+        assert_eq!(
+            key.check_at(&Method::GET, path, &reordered, before),
+            Presigned::Valid
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use http::{HeaderMap, HeaderValue, StatusCode, Uri, header};
+
+use crate::Config;
+use crate::content_negotiation::{NegotiatedPath, PathExtensions};
+use crate::encoding;
+use crate::methods;
+use crate::storage::StorageBackend;
+use crate::versioning::VersionHistory;
+use crate::webdav;
+
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-10.3
+pub const DESTINATION_HEADER: &str = "destination";
+
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-10.6
+pub const OVERWRITE_HEADER: &str = "overwrite";
+
+/// The path named by the *destination* header, given as an absolute URI or an absolute path.
+pub fn destination(headers: &HeaderMap) -> Option<PathBuf> {
+    let value = headers.get(DESTINATION_HEADER)?.to_str().ok()?;
+    let uri: Uri = value.parse().ok()?;
+    let path = uri.path();
+    path.starts_with('/').then(|| PathBuf::from(path))
+}
+
+/// Existing resources at the destination are replaced unless the *overwrite* header is "F".
+pub fn overwrite(headers: &HeaderMap) -> bool {
+    headers
+        .get(OVERWRITE_HEADER)
+        .is_none_or(|value| !value.as_bytes().eq_ignore_ascii_case(b"F"))
+}
+
+/// Copy every resource at or below `source` to the same relative path below `destination`,
+/// along with all of its representations and their extension entries, in a single batch update.
+/// The originals are deleted if `remove` is set, which makes it a move.
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-9.8
+pub fn relocate(
+    source: &Path,
+    destination: &Path,
+    remove: bool,
+    overwrite: bool,
+    config: &Config,
+    db: Arc<impl StorageBackend>,
+) -> Result<(StatusCode, Option<HeaderMap>)> {
+    // a WebDAV client names a collection with a trailing slash, such as "/v1.2/"
+    let resource = |path: &Path| match path.to_string_lossy().ends_with('/') {
+        true => webdav::trimmed(path),
+        false => crate::util::path_stem(path),
+    };
+    let source = resource(source);
+    let destination = resource(destination);
+    // a subtree can not be relocated into itself, nor over one of its ancestors
+    if destination.starts_with(&source) || source.starts_with(&destination) {
+        return Ok((StatusCode::FORBIDDEN, None));
+    }
+
+    let relocated = |from: PathBuf| {
+        let relative = from.strip_prefix(&source).unwrap().to_owned();
+        let to = match relative.as_os_str().is_empty() {
+            true => destination.clone(),
+            false => destination.join(relative),
+        };
+        (from, to)
+    };
+    let relocations: Vec<(PathBuf, PathBuf)> = PathExtensions::resources(&source, db.clone())?
+        .into_iter()
+        .map(relocated)
+        .collect();
+    // empty collections created by WebDAV clients are relocated along with the resources
+    let collections: Vec<(PathBuf, PathBuf)> = webdav::collections(&source, db.clone())?
+        .into_iter()
+        .map(relocated)
+        .collect();
+    if relocations.is_empty() && collections.is_empty() {
+        return Ok((StatusCode::NOT_FOUND, None));
+    }
+
+    let mut existing = false;
+    for (_, to) in relocations.iter() {
+        let extensions = PathExtensions::get_for_path(to, db.clone());
+        existing |= extensions.entries().next().is_some();
+    }
+    for (_, to) in collections.iter() {
+        existing |= db.get(webdav::collection_key(to))?.is_some();
+    }
+    if existing && !overwrite {
+        return Ok((StatusCode::PRECONDITION_FAILED, None));
+    }
+    // write-once resources can not be moved away, nor replaced
+    let protected = |(from, to): &(PathBuf, PathBuf)| {
+        let replaced = PathExtensions::get_for_path(to, db.clone())
+            .entries()
+            .next()
+            .is_some();
+        (remove && config.write_once.protects(from)) || (replaced && config.write_once.protects(to))
+    };
+    if relocations.iter().any(protected) {
+        let (status, headers) = methods::method_not_allowed(source, true, config);
+        return Ok((status, Some(headers)));
+    }
+
+    let mut updates = vec![];
+    for (from, to) in relocations.iter() {
+        updates.extend(relocate_resource(from, to, remove, config, db.clone())?);
+    }
+    for (from, to) in collections.iter() {
+        if remove {
+            updates.push((webdav::collection_key(from), None));
+        }
+        updates.push((webdav::collection_key(to), Some(vec![])));
+    }
+    db.batch_update(updates)?;
+
+    let headers = HeaderMap::from_iter([(
+        header::LOCATION,
+        HeaderValue::from_str(&destination.to_string_lossy())?,
+    )]);
+    match existing {
+        false => Ok((StatusCode::CREATED, Some(headers))),
+        true => Ok((StatusCode::NO_CONTENT, Some(headers))),
+    }
+}
+
+/// Returns the storage operations which copy every representation of the resource at `from`
+/// to the resource at `to`, replacing the representations it had before.
+fn relocate_resource(
+    from: &Path,
+    to: &Path,
+    remove: bool,
+    config: &Config,
+    db: Arc<impl StorageBackend>,
+) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+    let mut source = PathExtensions::get_for_path(from, db.clone());
+    let mut target = PathExtensions::get_for_path(to, db.clone());
+    let copied: Vec<(String, serde_json::Value)> = source
+        .entries()
+        .map(|(ext, entry)| (ext.clone(), entry.clone()))
+        .collect();
+    let replaced: Vec<String> = target
+        .entries()
+        .map(|(ext, _)| ext.clone())
+        .filter(|ext| !copied.iter().any(|(copied, _)| copied == ext))
+        .collect();
+    let media_type_of = |extensions: &PathExtensions, ext: &str| -> Result<String> {
+        Ok(extensions
+            .get_media_type(ext)?
+            .map(|mt| mt.to_string())
+            .unwrap_or(NegotiatedPath::GENERIC_MEDIA_TYPE.to_string()))
+    };
+
+    let mut updates = vec![];
+    let mut target_update = None;
+    for ext in replaced.iter() {
+        let key = target.key_of(ext);
+        let previous = db.get(&key)?;
+        updates.extend(encoding::remove_variants(&key, db.clone())?);
+        if let Some(limit) = config.keep_versions {
+            let previous = previous.map(|p| (p, media_type_of(&target, ext).unwrap_or_default()));
+            let mut history = VersionHistory::get_for_key(&key, db.clone());
+            updates.extend(history.record_delete(previous, limit)?);
+        }
+        updates.push((key, None));
+        let (k, v) = target.remove(ext)?;
+        target_update = Some((k.to_owned(), v));
+    }
+
+    for (ext, entry) in copied.iter() {
+        let from_key = source.key_of(ext);
+        let to_key = target.key_of(ext);
+        let Some(value) = db.get(&from_key)? else {
+            continue;
+        };
+        let media_type = media_type_of(&source, ext)?;
+
+        if let Some(limit) = config.keep_versions {
+            let previous = match target.get_entry(ext) {
+                Some(_) => db
+                    .get(&to_key)?
+                    .map(|p| (p, media_type_of(&target, ext).unwrap_or_default())),
+                None => None,
+            };
+            let mut history = VersionHistory::get_for_key(&to_key, db.clone());
+            updates.extend(history.record_put(&media_type, previous, limit)?);
+            if remove {
+                let mut history = VersionHistory::get_for_key(&from_key, db.clone());
+                updates.extend(history.record_delete(Some((value.clone(), media_type)), limit)?);
+            }
+        }
+        updates.extend(encoding::remove_variants(&to_key, db.clone())?);
+        updates.extend(encoding::copy_variants(&from_key, &to_key, db.clone())?);
+        if remove {
+            updates.extend(encoding::remove_variants(&from_key, db.clone())?);
+        }
+        updates.push((to_key, Some(value)));
+        let (k, v) = target.insert_entry(ext, entry.clone())?;
+        target_update = Some((k.to_owned(), v));
+    }
+    updates.extend(target_update);
+
+    if remove {
+        let extensions: Vec<String> = copied.into_iter().map(|(ext, _)| ext).collect();
+        updates.extend(source.remove_representations(&extensions)?);
+    }
+    Ok(updates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_destination() {
+        let headers = |value: &'static str| {
+            HeaderMap::from_iter([(
+                DESTINATION_HEADER.try_into().unwrap(),
+                HeaderValue::from_static(value),
+            )])
+        };
+        assert_eq!(
+            destination(&headers("https://localhost:8443/docs/b.txt")),
+            Some(PathBuf::from("/docs/b.txt"))
+        );
+        assert_eq!(
+            destination(&headers("/docs/b.txt")),
+            Some(PathBuf::from("/docs/b.txt"))
+        );
+        assert_eq!(destination(&headers("docs/b.txt")), None);
+        assert_eq!(destination(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let headers = |value: &'static str| {
+            HeaderMap::from_iter([(
+                OVERWRITE_HEADER.try_into().unwrap(),
+                HeaderValue::from_static(value),
+            )])
+        };
+        assert!(overwrite(&HeaderMap::new()));
+        assert!(overwrite(&headers("T")));
+        assert!(!overwrite(&headers("F")));
+    }
+}
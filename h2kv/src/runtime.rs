@@ -2,10 +2,14 @@ use std::fs::File;
 use std::io::{BufReader, Write};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, mpsc::Receiver};
+use std::time::Duration;
 
 use anyhow::{Result, anyhow, bail};
 
-use crate::{Config, IgnoreFilter, StorageBackend, fs_sync};
+use crate::{Config, IgnoreFilter, StorageBackend, expiry, fs_sync, trash};
+
+/// How often expired objects are deleted from storage.
+const EXPIRY_SWEEP_PERIOD: Duration = Duration::from_secs(30);
 
 /// On success, returns `Ok(None)` to parent and `Ok(Some(resources))` to daemon.
 pub fn spawn_daemon<F, L>(config: &Config, lock_resources: F) -> Result<Option<L>>
@@ -109,3 +113,31 @@ impl<'a> FilesystemActions<'a> {
         Ok(())
     }
 }
+
+/// Start periodic maintenance of the database in the background.
+/// Must be called from within the async runtime.
+pub fn spawn_maintenance(db: Arc<impl StorageBackend>, config: &Config) {
+    let expiry_db = db.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_SWEEP_PERIOD);
+        loop {
+            interval.tick().await;
+            if let Err(e) = expiry::sweep(expiry_db.clone()) {
+                log::error!("failed to delete expired objects: {e}");
+            }
+        }
+    });
+
+    if let Some(retention) = config.trash_retention {
+        let period = retention.clamp(Duration::from_secs(1), Duration::from_secs(60));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = trash::purge(retention, db.clone()) {
+                    log::error!("failed to purge trash: {e}");
+                }
+            }
+        });
+    }
+}
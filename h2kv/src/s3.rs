@@ -0,0 +1,588 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+
+use crate::conditional;
+use crate::content_negotiation::PathExtensions;
+use crate::storage::StorageBackend;
+use crate::util::{self, xml_escape};
+use crate::versioning::VersionHistory;
+
+/// Storage keys of users always start with "/", so keys in this namespace can not be addressed.
+/// The parts of a multipart upload are kept here until it is completed or aborted.
+pub const NAMESPACE: &str = "uploads";
+
+const XMLNS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+
+/// A path-style request to the S3 API, with each bucket mapped to the key prefix of its name.
+/// https://docs.aws.amazon.com/AmazonS3/latest/API/API_Operations_Amazon_Simple_Storage_Service.html
+#[derive(Debug, PartialEq)]
+pub enum Operation {
+    /// GetObject, HeadObject, PutObject or DeleteObject, served by the native handler of the path.
+    Object(PathBuf),
+    /// CreateBucket or HeadBucket, which always succeed because buckets exist implicitly.
+    Bucket(String),
+    ListObjects(String),
+    CreateUpload(PathBuf),
+    /// Operations on a multipart upload, which belongs to the object at `path`.
+    UploadPart {
+        path: PathBuf,
+        upload_id: String,
+        part: u32,
+    },
+    CompleteUpload {
+        path: PathBuf,
+        upload_id: String,
+    },
+    AbortUpload {
+        path: PathBuf,
+        upload_id: String,
+    },
+}
+
+impl Operation {
+    /// Identify the S3 operation of a request for `path` below `endpoint`.
+    pub fn parse(
+        endpoint: &Path,
+        method: &Method,
+        path: &Path,
+        query: &[(String, String)],
+    ) -> Option<Self> {
+        let mut components = path.strip_prefix(endpoint).ok()?.components();
+        let bucket = components.next()?.as_os_str().to_str()?.to_string();
+        let key = components.as_path();
+        let param = |name: &str| {
+            query
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.clone())
+        };
+
+        if key.as_os_str().is_empty() {
+            return match *method {
+                Method::GET => Some(Self::ListObjects(bucket)),
+                Method::HEAD | Method::PUT => Some(Self::Bucket(bucket)),
+                _ => None,
+            };
+        }
+
+        // a key such as "photos/" would name a collection in the native interface
+        if path.to_string_lossy().ends_with('/') {
+            return None;
+        }
+        let path = Path::new("/").join(&bucket).join(key);
+        match (method.clone(), param("uploadId")) {
+            (Method::POST, None) if param("uploads").is_some() => Some(Self::CreateUpload(path)),
+            (Method::PUT, Some(upload_id)) => Some(Self::UploadPart {
+                path,
+                upload_id,
+                part: param("partNumber")?.parse().ok()?,
+            }),
+            (Method::POST, Some(upload_id)) => Some(Self::CompleteUpload { path, upload_id }),
+            (Method::DELETE, Some(upload_id)) => Some(Self::AbortUpload { path, upload_id }),
+            (Method::GET | Method::HEAD | Method::PUT | Method::DELETE, None) => {
+                Some(Self::Object(path))
+            }
+            _ => None,
+        }
+    }
+
+    /// The method and path of the native request which the operation is authorized as.
+    /// A bucket is listed like the collection of its key prefix, and a multipart upload
+    /// is authorized as the PUT of its object, which it eventually writes.
+    pub fn authorized_as(&self, method: &Method) -> (Method, PathBuf) {
+        match self {
+            Self::Object(path) => (method.clone(), path.clone()),
+            Self::Bucket(bucket) | Self::ListObjects(bucket) => {
+                (method.clone(), PathBuf::from(format!("/{bucket}/")))
+            }
+            Self::CreateUpload(path)
+            | Self::UploadPart { path, .. }
+            | Self::CompleteUpload { path, .. }
+            | Self::AbortUpload { path, .. } => (Method::PUT, path.clone()),
+        }
+    }
+}
+
+/// The contents of an object written with PutObject or UploadPart.
+/// The AWS CLI sends checksums in trailers, with *content-encoding: aws-chunked*.
+/// https://docs.aws.amazon.com/AmazonS3/latest/API/sigv4-streaming.html
+pub fn object_body(headers: &mut HeaderMap, body: Vec<u8>) -> Result<Vec<u8>> {
+    let Some(encoding) = headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+    else {
+        return Ok(body);
+    };
+    let codings: Vec<&str> = encoding.split(',').map(str::trim).collect();
+    if !codings.contains(&"aws-chunked") {
+        return Ok(body);
+    }
+
+    let remaining: Vec<&str> = codings
+        .into_iter()
+        .filter(|c| !c.is_empty() && *c != "aws-chunked")
+        .collect();
+    headers.remove(header::CONTENT_ENCODING);
+    if !remaining.is_empty() {
+        headers.insert(header::CONTENT_ENCODING, remaining.join(", ").parse()?);
+    }
+    decode_chunks(&body)
+}
+
+/// A listing of the objects in `bucket`, according to the parameters of ListObjectsV2.
+/// https://docs.aws.amazon.com/AmazonS3/latest/API/API_ListObjectsV2.html
+pub fn list_objects(
+    bucket: &str,
+    query: &[(String, String)],
+    db: Arc<impl StorageBackend>,
+) -> Result<(StatusCode, Option<HeaderMap>, Option<Bytes>)> {
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    };
+    let prefix = param("prefix").unwrap_or_default();
+    let delimiter = param("delimiter").filter(|d| !d.is_empty());
+    let max_keys: usize = param("max-keys")
+        .and_then(|m| m.parse().ok())
+        .unwrap_or(1000);
+    let token = param("continuation-token");
+    let start_after = token.or(param("start-after")).unwrap_or_default();
+
+    // every representation is an object, named by the path at which it can be requested
+    let bucket_path = Path::new("/").join(bucket);
+    let mut objects = vec![];
+    for resource in PathExtensions::resources(&bucket_path, db.clone())? {
+        let extensions = PathExtensions::get_for_path(&resource, db.clone());
+        for representation in extensions.representations()? {
+            let location = extensions.location_of(representation.extension);
+            let Ok(key) = location.strip_prefix(&bucket_path) else {
+                continue;
+            };
+            let key = key.to_string_lossy().to_string();
+            if key.starts_with(prefix) && key.as_str() > start_after {
+                objects.push((key, extensions.key_of(representation.extension)));
+            }
+        }
+    }
+    objects.sort();
+
+    let mut contents = String::new();
+    let mut common_prefixes = BTreeSet::new();
+    let mut key_count = 0;
+    let mut last_key = None;
+    let mut truncated = false;
+    for (key, storage_key) in objects.iter() {
+        // objects which share a common prefix are listed once, as the prefix
+        let common_prefix = delimiter.and_then(|delimiter| {
+            let rest = &key[prefix.len()..];
+            rest.find(delimiter)
+                .map(|i| key[..prefix.len() + i + delimiter.len()].to_string())
+        });
+        if common_prefix
+            .as_ref()
+            .is_some_and(|p| common_prefixes.contains(p))
+        {
+            last_key = Some(key);
+            continue;
+        }
+        if key_count == max_keys {
+            truncated = true;
+            break;
+        }
+        key_count += 1;
+        last_key = Some(key);
+
+        match common_prefix {
+            Some(common_prefix) => {
+                common_prefixes.insert(common_prefix);
+            }
+            None => {
+                let value = db.get(storage_key)?.unwrap_or_default();
+                let modified = VersionHistory::get_for_key(storage_key, db.clone())
+                    .latest()
+                    .map(|v| util::from_micros(v.id))
+                    .unwrap_or(UNIX_EPOCH);
+                write!(
+                    contents,
+                    "<Contents><Key>{}</Key><LastModified>{}</LastModified>\
+                    <ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+                    xml_escape(key),
+                    timestamp(modified),
+                    xml_escape(conditional::etag(&value).to_str()?),
+                    value.len(),
+                )?;
+            }
+        }
+    }
+
+    let mut body = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    write!(body, r#"<ListBucketResult xmlns="{XMLNS}">"#)?;
+    write!(body, "<Name>{}</Name>", xml_escape(bucket))?;
+    write!(body, "<Prefix>{}</Prefix>", xml_escape(prefix))?;
+    if let Some(delimiter) = delimiter {
+        write!(body, "<Delimiter>{}</Delimiter>", xml_escape(delimiter))?;
+    }
+    // keys are listed as they were requested, which is already url-encoded
+    if let Some(encoding_type) = param("encoding-type") {
+        write!(
+            body,
+            "<EncodingType>{}</EncodingType>",
+            xml_escape(encoding_type)
+        )?;
+    }
+    write!(
+        body,
+        "<MaxKeys>{max_keys}</MaxKeys><KeyCount>{key_count}</KeyCount>"
+    )?;
+    write!(body, "<IsTruncated>{truncated}</IsTruncated>")?;
+    if let Some(token) = token {
+        write!(
+            body,
+            "<ContinuationToken>{}</ContinuationToken>",
+            xml_escape(token)
+        )?;
+    }
+    if let (true, Some(last_key)) = (truncated, last_key) {
+        let next = xml_escape(last_key);
+        write!(
+            body,
+            "<NextContinuationToken>{next}</NextContinuationToken>"
+        )?;
+    }
+    body.push_str(&contents);
+    for common_prefix in common_prefixes {
+        let common_prefix = xml_escape(&common_prefix);
+        write!(
+            body,
+            "<CommonPrefixes><Prefix>{common_prefix}</Prefix></CommonPrefixes>"
+        )?;
+    }
+    body.push_str("</ListBucketResult>");
+
+    Ok(xml_response(StatusCode::OK, body))
+}
+
+/// Start a multipart upload to `path`, recording the content-type it will be stored with.
+/// https://docs.aws.amazon.com/AmazonS3/latest/API/API_CreateMultipartUpload.html
+pub fn create_upload(
+    path: &Path,
+    headers: &HeaderMap,
+    db: Arc<impl StorageBackend>,
+) -> Result<(StatusCode, Option<HeaderMap>, Option<Bytes>)> {
+    let started = util::micros(SystemTime::now());
+    let upload_id = format!("{:x}", md5::compute(format!("{path:?} {started}")));
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let upload = serde_json::json!({
+        "path": path.to_string_lossy(),
+        "content-type": content_type,
+    });
+    db.put(upload_key(&upload_id), &serde_json::to_vec(&upload)?)?;
+
+    let mut components = path.components();
+    components.next();
+    let bucket = components.next().unwrap().as_os_str().to_string_lossy();
+    let key = components.as_path().to_string_lossy();
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><InitiateMultipartUploadResult xmlns="{XMLNS}">{}</InitiateMultipartUploadResult>"#,
+        format_args!(
+            "<Bucket>{}</Bucket><Key>{}</Key><UploadId>{upload_id}</UploadId>",
+            xml_escape(&bucket),
+            xml_escape(&key),
+        )
+    );
+    Ok(xml_response(StatusCode::OK, body))
+}
+
+/// Store a part of a multipart upload.
+/// https://docs.aws.amazon.com/AmazonS3/latest/API/API_UploadPart.html
+pub fn upload_part(
+    path: &Path,
+    upload_id: &str,
+    part: u32,
+    data: &[u8],
+    db: Arc<impl StorageBackend>,
+) -> Result<(StatusCode, Option<HeaderMap>)> {
+    if upload(path, upload_id, db.clone())?.is_none() {
+        return Ok((StatusCode::NOT_FOUND, None));
+    }
+    db.put(part_key(upload_id, part), data)?;
+
+    let mut headers = HeaderMap::new();
+    headers.append(header::ETAG, conditional::etag(data));
+    Ok((StatusCode::OK, Some(headers)))
+}
+
+/// An object assembled from the parts of a multipart upload.
+pub struct Upload {
+    pub path: PathBuf,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// Assemble the parts named in the body of a CompleteMultipartUpload `request`, in order,
+/// and discard the upload. Returns `None` if the upload of `path` or one of its parts does not exist.
+/// https://docs.aws.amazon.com/AmazonS3/latest/API/API_CompleteMultipartUpload.html
+pub fn complete_upload(
+    path: &Path,
+    upload_id: &str,
+    request: &[u8],
+    db: Arc<impl StorageBackend>,
+) -> Result<Option<Upload>> {
+    let Some(upload) = upload(path, upload_id, db.clone())? else {
+        return Ok(None);
+    };
+
+    let request = String::from_utf8_lossy(request);
+    let parts: Vec<u32> = request
+        .split("<PartNumber>")
+        .skip(1)
+        .filter_map(|s| s.split("</PartNumber>").next()?.trim().parse().ok())
+        .collect();
+    if parts.is_empty() {
+        return Ok(None);
+    }
+    let mut body = vec![];
+    for part in parts {
+        match db.get(part_key(upload_id, part))? {
+            Some(data) => body.extend(data),
+            None => return Ok(None),
+        }
+    }
+
+    let mut headers = HeaderMap::new();
+    if let Some(content_type) = upload["content-type"].as_str() {
+        headers.append(header::CONTENT_TYPE, content_type.parse()?);
+    }
+    discard_upload(upload_id, db)?;
+    Ok(Some(Upload {
+        path: path.to_owned(),
+        headers,
+        body,
+    }))
+}
+
+/// Discard a multipart upload along with its parts.
+/// https://docs.aws.amazon.com/AmazonS3/latest/API/API_AbortMultipartUpload.html
+pub fn abort_upload(
+    path: &Path,
+    upload_id: &str,
+    db: Arc<impl StorageBackend>,
+) -> Result<StatusCode> {
+    if upload(path, upload_id, db.clone())?.is_none() {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+    discard_upload(upload_id, db)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn discard_upload(upload_id: &str, db: Arc<impl StorageBackend>) -> Result<()> {
+    let parts = db.keys(format!("{NAMESPACE}/{upload_id}/"))?;
+    let updates = parts
+        .into_iter()
+        .chain([upload_key(upload_id)])
+        .map(|k| (k, None::<Vec<u8>>));
+    db.batch_update(updates)
+}
+
+/// The record of the multipart upload `upload_id`, if it exists and belongs to the object at `path`.
+fn upload(
+    path: &Path,
+    upload_id: &str,
+    db: Arc<impl StorageBackend>,
+) -> Result<Option<serde_json::Value>> {
+    let Some(upload) = db.get(upload_key(upload_id))? else {
+        return Ok(None);
+    };
+    let upload: serde_json::Value = serde_json::from_slice(&upload)?;
+    let upload_path = upload["path"]
+        .as_str()
+        .ok_or_else(|| anyhow!("upload {upload_id} has no path"))?;
+    Ok((Path::new(upload_path) == path).then_some(upload))
+}
+
+fn upload_key(upload_id: &str) -> PathBuf {
+    PathBuf::from(format!("{NAMESPACE}/{upload_id}"))
+}
+
+fn part_key(upload_id: &str, part: u32) -> PathBuf {
+    PathBuf::from(format!("{NAMESPACE}/{upload_id}/{part:05}"))
+}
+
+fn xml_response(
+    status: StatusCode,
+    body: String,
+) -> (StatusCode, Option<HeaderMap>, Option<Bytes>) {
+    let mut headers = HeaderMap::new();
+    headers.append(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/xml"),
+    );
+    headers.append(header::CONTENT_LENGTH, body.len().into());
+    (status, Some(headers), Some(body.into()))
+}
+
+fn decode_chunks(mut body: &[u8]) -> Result<Vec<u8>> {
+    let mut decoded = vec![];
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow!("unterminated chunk header"))?;
+        // the size may be followed by a signature, such as "400;chunk-signature=..."
+        let size = std::str::from_utf8(&body[..line_end])?;
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or_default().trim(), 16)?;
+        body = &body[line_end + 2..];
+        // the last chunk is followed by trailers, which are not used
+        if size == 0 {
+            return Ok(decoded);
+        }
+        if body.len() < size + 2 {
+            bail!("truncated chunk");
+        }
+        decoded.extend_from_slice(&body[..size]);
+        body = &body[size + 2..];
+    }
+}
+
+/// ISO 8601 representation of `time` in UTC, such as "2009-10-12T17:50:30.000Z".
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let days = (seconds / 86400) as i64;
+
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds % 86400 / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let endpoint = Path::new("/s3");
+        let parse = |method: Method, path: &str, query: &[(&str, &str)]| {
+            let query: Vec<(String, String)> = query
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            Operation::parse(endpoint, &method, Path::new(path), &query)
+        };
+
+        assert_eq!(
+            parse(Method::GET, "/s3/photos/2024/a.jpg", &[]),
+            Some(Operation::Object(PathBuf::from("/photos/2024/a.jpg")))
+        );
+        assert_eq!(
+            parse(Method::GET, "/s3/photos", &[("list-type", "2")]),
+            Some(Operation::ListObjects("photos".to_string()))
+        );
+        assert_eq!(
+            parse(Method::PUT, "/s3/photos/", &[]),
+            Some(Operation::Bucket("photos".to_string()))
+        );
+        assert_eq!(
+            parse(Method::POST, "/s3/photos/a.jpg", &[("uploads", "")]),
+            Some(Operation::CreateUpload(PathBuf::from("/photos/a.jpg")))
+        );
+        assert_eq!(
+            parse(
+                Method::PUT,
+                "/s3/photos/a.jpg",
+                &[("partNumber", "2"), ("uploadId", "abc")]
+            ),
+            Some(Operation::UploadPart {
+                path: PathBuf::from("/photos/a.jpg"),
+                upload_id: "abc".to_string(),
+                part: 2
+            })
+        );
+        assert_eq!(
+            parse(Method::POST, "/s3/photos/a.jpg", &[("uploadId", "abc")]),
+            Some(Operation::CompleteUpload {
+                path: PathBuf::from("/photos/a.jpg"),
+                upload_id: "abc".to_string()
+            })
+        );
+        assert_eq!(parse(Method::GET, "/photos/a.jpg", &[]), None);
+
+        let authorized_as = |method: Method, path: &str, query: &[(&str, &str)]| {
+            parse(method.clone(), path, query).map(|op| op.authorized_as(&method))
+        };
+        assert_eq!(
+            authorized_as(Method::DELETE, "/s3/photos/a.jpg", &[]),
+            Some((Method::DELETE, PathBuf::from("/photos/a.jpg")))
+        );
+        assert_eq!(
+            authorized_as(Method::GET, "/s3/photos", &[("list-type", "2")]),
+            Some((Method::GET, PathBuf::from("/photos/")))
+        );
+        assert_eq!(
+            authorized_as(Method::POST, "/s3/photos/a.jpg", &[("uploadId", "abc")]),
+            Some((Method::PUT, PathBuf::from("/photos/a.jpg")))
+        );
+        assert_eq!(parse(Method::PUT, "/s3/photos/2024/", &[]), None);
+        assert_eq!(parse(Method::GET, "/s3", &[]), None);
+    }
+
+    #[test]
+    fn test_object_body() {
+        let mut headers = HeaderMap::from_iter([(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static("aws-chunked, gzip"),
+        )]);
+        let body =
+            b"5;chunk-signature=abc\r\nhello\r\n3\r\n!!!\r\n0\r\nx-amz-checksum-crc32:AAAA\r\n\r\n";
+        let decoded = object_body(&mut headers, body.to_vec()).unwrap();
+        assert_eq!(decoded, b"hello!!!");
+        assert_eq!(headers.get(header::CONTENT_ENCODING).unwrap(), "gzip");
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            object_body(&mut headers, b"plain".to_vec()).unwrap(),
+            b"plain"
+        );
+        assert!(decode_chunks(b"5\r\nhel").is_err());
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            timestamp(util::from_micros(784_111_777_250_000)),
+            "1994-11-06T08:49:37.250Z"
+        );
+        assert_eq!(
+            timestamp(util::from_micros(1_709_210_096_000_000)),
+            "2024-02-29T12:34:56.000Z"
+        );
+    }
+}
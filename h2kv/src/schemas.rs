@@ -0,0 +1,186 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+use mediatype::MediaType;
+
+use crate::storage::StorageBackend;
+
+/// JSON Schemas by path prefix, which JSON objects written under the prefix must conform to.
+/// The schemas are objects themselves, so that they can be managed like any other.
+#[derive(Debug, Clone, Default)]
+pub struct Schemas {
+    /// Storage key of the schema by path prefix, longest prefix first.
+    prefixes: Vec<(String, PathBuf)>,
+}
+
+/// A part of an object which does not conform to its schema.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    /// JSON Pointer to the offending value in the object.
+    pub instance_path: String,
+    /// JSON Pointer to the keyword of the schema which the value violates.
+    pub schema_path: String,
+    pub message: String,
+}
+
+impl Schemas {
+    pub const ENV_NAME: &str = "H2KV_SCHEMAS";
+    pub const ENV_DESCRIPTION: &str = r#"
+    Validation of JSON objects written under a path prefix against a JSON Schema.
+    Format:
+    Lines of a path prefix followed by the key of the object holding the schema,
+    including its extension. The line with the longest prefix applies.
+    Objects which do not conform are rejected with 422 Unprocessable Content.
+    Comments allowed between '#' and end of line.
+    Example: "/config/ /schemas/config.json"
+    "#;
+
+    pub fn try_from_env() -> Result<Self> {
+        match std::env::var(Self::ENV_NAME) {
+            Ok(schemas) => Self::try_from_str(&schemas),
+            Err(std::env::VarError::NotPresent) => Ok(Self::default()),
+            Err(e) => Err(anyhow!(
+                "unparsed environment variable {}: {e}",
+                Self::ENV_NAME
+            )),
+        }
+    }
+
+    pub fn try_from_str(schemas: &str) -> Result<Self> {
+        let mut prefixes = vec![];
+        for line in schemas.lines().flat_map(|line| line.split("\\n")) {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((prefix, key)) = line.split_once(char::is_whitespace) else {
+                bail!("expected a path prefix and a schema key: '{line}'");
+            };
+            let key = Path::new(key.trim());
+            if !key.starts_with("/") || key.extension().is_none() {
+                bail!("schema key for {prefix:?} should be absolute with an extension: {key:?}");
+            }
+            prefixes.push((prefix.to_string(), key.to_owned()));
+        }
+        prefixes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Ok(Self { prefixes })
+    }
+
+    /// The storage key of the schema which applies to `path`, if any.
+    pub fn schema_for(&self, path: &Path) -> Option<&Path> {
+        let path = path.to_string_lossy();
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map(|(_, key)| key.as_path())
+    }
+
+    /// Validate `data` written to storage `key` against the schema of its prefix, if it is JSON.
+    /// A schema which has not been stored yet does not constrain anything.
+    pub fn violations(
+        &self,
+        key: &Path,
+        media_type: &MediaType,
+        data: &[u8],
+        db: Arc<impl StorageBackend>,
+    ) -> Result<Vec<Violation>> {
+        let Some(schema_key) = self.schema_for(key) else {
+            return Ok(vec![]);
+        };
+        if !is_json(media_type) || key == schema_key {
+            return Ok(vec![]);
+        }
+        let Some(schema) = db.get(schema_key)? else {
+            log::warn!("schema {schema_key:?} for {key:?} not found");
+            return Ok(vec![]);
+        };
+        let schema: serde_json::Value = serde_json::from_slice(&schema)
+            .map_err(|e| anyhow!("schema {schema_key:?} is not JSON: {e}"))?;
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|e| anyhow!("schema {schema_key:?} is invalid: {e}"))?;
+
+        let instance: serde_json::Value = match serde_json::from_slice(data) {
+            Ok(instance) => instance,
+            Err(e) => {
+                return Ok(vec![Violation {
+                    instance_path: String::new(),
+                    schema_path: String::new(),
+                    message: format!("not valid JSON: {e}"),
+                }]);
+            }
+        };
+        Ok(validator
+            .iter_errors(&instance)
+            .map(|e| Violation {
+                instance_path: e.instance_path().to_string(),
+                schema_path: e.schema_path().to_string(),
+                message: e.to_string(),
+            })
+            .collect())
+    }
+}
+
+/// A JSON listing of the `violations` which prevented an object from being written.
+pub fn unprocessable(
+    violations: &[Violation],
+) -> Result<(StatusCode, Option<HeaderMap>, Option<Bytes>)> {
+    let listing: Vec<_> = violations
+        .iter()
+        .map(|v| {
+            serde_json::json!({
+                "instance-path": v.instance_path,
+                "schema-path": v.schema_path,
+                "message": v.message,
+            })
+        })
+        .collect();
+
+    let listing = serde_json::to_vec(&listing)?;
+    let mut headers = HeaderMap::new();
+    headers.append(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.append(header::CONTENT_LENGTH, listing.len().into());
+    Ok((
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Some(headers),
+        Some(listing.into()),
+    ))
+}
+
+fn is_json(media_type: &MediaType) -> bool {
+    (media_type.ty == "application" && media_type.subty == "json")
+        || media_type.suffix.is_some_and(|s| s == "json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_for() {
+        let schemas = Schemas::try_from_str(
+            r#"
+            /config/ /schemas/config.json
+            /config/app/ /schemas/app.json  # more specific
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            schemas.schema_for(Path::new("/config/app/settings")),
+            Some(Path::new("/schemas/app.json"))
+        );
+        assert_eq!(
+            schemas.schema_for(Path::new("/config/db")),
+            Some(Path::new("/schemas/config.json"))
+        );
+        assert_eq!(schemas.schema_for(Path::new("/other")), None);
+
+        assert!(Schemas::try_from_str("/config/ /schemas/config").is_err());
+        assert!(Schemas::try_from_str("/config/").is_err());
+    }
+}
@@ -1,24 +1,47 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::Result;
 use bytes::{BufMut, Bytes};
 use h2::RecvStream;
 use h2::server::{self, SendResponse};
-use http::{HeaderMap, Method, Request, Response, StatusCode, Version, header};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri, Version, header};
+use mediatype::{ReadParams, names::CHARSET};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Instant;
 
-use crate::content_negotiation::{NegotiatedPath, PathExtensions};
+use crate::Config;
+use crate::conditional;
+use crate::content_negotiation::{self, NegotiatedPath, PathExtensions};
+use crate::encoding;
+use crate::expiry;
+use crate::metadata;
+use crate::methods;
+use crate::pointer;
+use crate::presign::Presigned;
+use crate::relocation;
+use crate::s3;
+use crate::schemas;
 use crate::storage::StorageBackend;
+use crate::transcoding::Format;
+use crate::trash;
+use crate::versioning::{self, VersionHistory};
+use crate::webdav;
+use crate::write_protection;
 
-pub async fn listen(listener: &TcpListener, db: Arc<impl StorageBackend>) -> Result<()> {
+pub async fn listen(
+    listener: &TcpListener,
+    db: Arc<impl StorageBackend>,
+    config: Arc<Config>,
+) -> Result<()> {
     log::info!("listening on {:?}", listener.local_addr()?);
 
     loop {
         if let Ok((socket, _peer_addr)) = listener.accept().await {
             let db = db.clone();
+            let config = config.clone();
             tokio::spawn(async move {
-                if let Err(e) = serve(socket, db).await {
+                if let Err(e) = serve(socket, db, config).await {
                     log::error!("H2 listener error: {e:?}");
                 }
             });
@@ -26,15 +49,16 @@ pub async fn listen(listener: &TcpListener, db: Arc<impl StorageBackend>) -> Res
     }
 }
 
-async fn serve(socket: TcpStream, db: Arc<impl StorageBackend>) -> Result<()> {
+async fn serve(socket: TcpStream, db: Arc<impl StorageBackend>, config: Arc<Config>) -> Result<()> {
     let mut connection = server::handshake(socket).await?;
     log::trace!("H2 connection opened");
 
     while let Some(result) = connection.accept().await {
         let (request, respond) = result?;
         let db = db.clone();
+        let config = config.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_request(request, respond, db).await {
+            if let Err(e) = handle_request(request, respond, db, config).await {
                 log::error!("error while handling request: {e}");
             }
         });
@@ -48,12 +72,19 @@ async fn handle_request(
     mut request: Request<RecvStream>,
     mut respond: SendResponse<Bytes>,
     db: Arc<impl StorageBackend>,
+    config: Arc<Config>,
 ) -> Result<()> {
-    let method = request.method().clone();
+    let mut method = request.method().clone();
     let uri = request.uri().clone();
-    let path = PathBuf::from(uri.path());
-    let headers = request.headers().clone();
+    let mut path = PathBuf::from(uri.path());
+    let query = query_pairs(&uri);
+    let mut headers = request.headers().clone();
     let body = request.body_mut();
+    // the body of a PUT request which has already been received
+    let mut received = None;
+
+    // every response can be read by scripts of an allowed origin
+    let cors = config.cors.response_headers(&headers);
 
     let mut response =
         |status: StatusCode, headers: Option<HeaderMap>, body: Option<Bytes>| -> Result<()> {
@@ -61,6 +92,9 @@ async fn handle_request(
             parts.version = Version::HTTP_2;
             parts.status = status;
             parts.headers = headers.unwrap_or_default();
+            for (name, value) in cors.iter() {
+                parts.headers.append(name, value.clone());
+            }
             let response = Response::from_parts(parts, ());
             log::trace!("sent {response:?}");
             let mut send = respond.send_response(response, false)?;
@@ -68,70 +102,514 @@ async fn handle_request(
             Ok(())
         };
 
+    // browsers do not send credentials with a preflight request
+    if let Some(preflight) = config.cors.preflight(&method, &headers) {
+        log::trace!("received preflight {path:?} with {headers:?}");
+        response(StatusCode::NO_CONTENT, Some(preflight), None)?;
+        return Ok(());
+    }
+
+    // the server as a whole has no resource to authorize
+    if method == Method::OPTIONS && uri.path() == "*" {
+        log::trace!("received OPTIONS * with {headers:?}");
+        response(
+            StatusCode::OK,
+            Some(methods::options(None, false, &config)),
+            None,
+        )?;
+        return Ok(());
+    }
+
+    // a pre-signed URL grants access to its own key, without a bearer token
+    let presigned = match &config.signing_key {
+        Some(key) => key.check(&method, &path, &query),
+        None => Presigned::Unsigned,
+    };
+    if let Presigned::Invalid(reason) = presigned {
+        log::info!("refused pre-signed {method} {path:?}: {reason}");
+        response(StatusCode::FORBIDDEN, None, None)?;
+        return Ok(());
+    }
+
+    if config.read_only && write_protection::is_write(&method) {
+        log::info!("refused {method} {path:?}: read-only");
+        // only the read methods are allowed, whether or not the resource exists,
+        // so the refusal does not read storage before the request is authorized
+        let (status, headers) = methods::method_not_allowed(&path, false, &config);
+        response(status, Some(headers), None)?;
+        return Ok(());
+    }
+
+    // requests through the S3 API are authorized as the native requests they are served by
+    let mut authorized = false;
+    if let Some(endpoint) = &config.s3_endpoint
+        && let Some(operation) = s3::Operation::parse(endpoint, &method, &path, &query)
+    {
+        log::trace!("received S3 {operation:?} with {headers:?}");
+        // scopes apply to the stored keys, which S3 requests reach by another path and method
+        let (authorized_method, authorized_path) = operation.authorized_as(&method);
+        if let Some((status, refusal)) = refusal(
+            &config,
+            &presigned,
+            &headers,
+            &authorized_method,
+            &authorized_path,
+            None,
+        ) {
+            log::info!("refused S3 {operation:?}: {status}");
+            response(status, Some(refusal), None)?;
+            return Ok(());
+        }
+        authorized = true;
+
+        match operation {
+            // objects are served by the native handlers of their paths
+            s3::Operation::Object(object) => {
+                if method == Method::PUT {
+                    match s3::object_body(&mut headers, read_body(body).await?) {
+                        Ok(data) => received = Some(data),
+                        Err(e) => {
+                            log::warn!("rejected S3 PUT {object:?}: {e}");
+                            response(StatusCode::BAD_REQUEST, None, None)?;
+                            return Ok(());
+                        }
+                    }
+                }
+                path = object;
+            }
+            s3::Operation::Bucket(_) => {
+                response(StatusCode::OK, None, None)?;
+                return Ok(());
+            }
+            s3::Operation::ListObjects(bucket) => {
+                let (status, headers, body) = s3::list_objects(&bucket, &query, db)?;
+                match method {
+                    Method::HEAD => response(status, headers, None)?,
+                    _ => response(status, headers, body)?,
+                }
+                return Ok(());
+            }
+            s3::Operation::CreateUpload(object) => {
+                let (status, headers, body) = s3::create_upload(&object, &headers, db)?;
+                response(status, headers, body)?;
+                return Ok(());
+            }
+            s3::Operation::UploadPart {
+                path: object,
+                upload_id,
+                part,
+            } => {
+                let (status, headers) = match s3::object_body(&mut headers, read_body(body).await?)
+                {
+                    Ok(data) => s3::upload_part(&object, &upload_id, part, &data, db)?,
+                    Err(e) => {
+                        log::warn!("rejected S3 part {part} of {upload_id}: {e}");
+                        (StatusCode::BAD_REQUEST, None)
+                    }
+                };
+                response(status, headers, None)?;
+                return Ok(());
+            }
+            // the assembled object is written by the native PUT handler of its path
+            s3::Operation::CompleteUpload {
+                path: object,
+                upload_id,
+            } => {
+                let request = read_body(body).await?;
+                let Some(upload) = s3::complete_upload(&object, &upload_id, &request, db.clone())?
+                else {
+                    response(StatusCode::BAD_REQUEST, None, None)?;
+                    return Ok(());
+                };
+                method = Method::PUT;
+                path = upload.path;
+                headers = upload.headers;
+                received = Some(upload.body);
+            }
+            s3::Operation::AbortUpload {
+                path: object,
+                upload_id,
+            } => {
+                let status = s3::abort_upload(&object, &upload_id, db)?;
+                response(status, None, None)?;
+                return Ok(());
+            }
+        }
+    }
+
+    // a request is authorized for its destination as well, before the storage is accessed
+    if !authorized {
+        let destination = match method.as_str() {
+            "COPY" | "MOVE" => relocation::destination(&headers),
+            _ => None,
+        };
+        if let Some((status, refusal)) = refusal(
+            &config,
+            &presigned,
+            &headers,
+            &method,
+            &path,
+            destination.as_deref(),
+        ) {
+            log::info!("refused {method} {path:?}: {status}");
+            response(status, Some(refusal), None)?;
+            return Ok(());
+        }
+    }
+
     match (method, path, headers) {
         (method @ (Method::HEAD | Method::GET), path, headers) => {
             log::trace!("received {method} {path:?} with {headers:?}");
-            let extensions = PathExtensions::get_for_path(&path, db.clone());
 
-            match NegotiatedPath::for_read(&path, &extensions, &headers)? {
-                None => response(StatusCode::NOT_FOUND, None, None)?,
-                Some(negotiated) => match db.get(&negotiated) {
-                    Ok(Some(data)) => {
-                        let mut headers = HeaderMap::new();
-                        headers.append(header::CONTENT_TYPE, negotiated.content_type_header());
-                        headers.append(header::CONTENT_LENGTH, data.len().into());
+            if config.trash_retention.is_some() && query.iter().any(|(k, _)| k == "trash") {
+                let (status, headers, body) = trash::list(&path, db)?;
+                match method {
+                    Method::HEAD => response(status, headers, None)?,
+                    _ => response(status, headers, body)?,
+                }
+                return Ok(());
+            }
+
+            if config.keep_versions.is_some() && versioning::is_requested(&query, &headers) {
+                let extensions = PathExtensions::get_for_path(&path, db.clone());
+                // a deleted representation can only be found in its history
+                let key = match NegotiatedPath::for_read(&path, &extensions, &headers, &config)? {
+                    Some(negotiated) => negotiated.source_key(),
+                    None if path.extension().is_some() => path,
+                    None => path.with_extension(NegotiatedPath::GENERIC_EXT),
+                };
+                let (status, headers, body) = versioning::read(&key, &query, &headers, db)?;
+                match method {
+                    Method::HEAD => response(status, headers, None)?,
+                    _ => response(status, headers, body)?,
+                }
+                return Ok(());
+            }
+
+            let pointer = pointer::requested(&query);
+            if pointer.is_some_and(|pointer| !pointer::is_valid(pointer)) {
+                response(StatusCode::BAD_REQUEST, None, None)?;
+                return Ok(());
+            }
+
+            // a long-poll request is parked until the representation changes
+            let wait = conditional::wait_preference(&headers);
+            let deadline = wait.map(|wait| Instant::now() + wait);
+            let mut changes = db.subscribe(PathExtensions::storage_key(&path));
+
+            loop {
+                let extensions = PathExtensions::get_for_path(&path, db.clone());
+
+                match NegotiatedPath::for_read(&path, &extensions, &headers, &config)? {
+                    // the resource exists, but none of its representations are acceptable,
+                    // or there is no accept header to choose between them
+                    None if path.extension().is_none()
+                        && !extensions.representations()?.is_empty() =>
+                    {
+                        let (status, headers, body) = match headers.get(header::ACCEPT) {
+                            Some(_) => content_negotiation::not_acceptable(&extensions)?,
+                            None => content_negotiation::multiple_choices(&extensions)?,
+                        };
                         match method {
-                            Method::HEAD => {
-                                response(StatusCode::OK, Some(headers), None)?;
+                            Method::HEAD => response(status, headers, None)?,
+                            _ => response(status, headers, body)?,
+                        }
+                    }
+                    None => response(StatusCode::NOT_FOUND, None, None)?,
+                    Some(negotiated) => match db.get(negotiated.source_key()) {
+                        Ok(Some(data)) => {
+                            let data = match negotiated.transcode() {
+                                None => data,
+                                Some(transcode) => match transcode.apply(&data) {
+                                    Ok(data) => data,
+                                    Err(e) => {
+                                        log::warn!("failed to convert {negotiated}: {e}");
+                                        let (status, headers, body) =
+                                            content_negotiation::not_acceptable(&extensions)?;
+                                        match method {
+                                            Method::HEAD => response(status, headers, None)?,
+                                            _ => response(status, headers, body)?,
+                                        }
+                                        break;
+                                    }
+                                },
+                            };
+                            // only the part of the representation referenced by the pointer is served
+                            let data = match pointer.map(|pointer| {
+                                pointer::resolve(negotiated.media_type(), &data, pointer)
+                            }) {
+                                None => data,
+                                Some(Ok(Some(fragment))) => fragment,
+                                Some(Ok(None)) => {
+                                    response(StatusCode::NOT_FOUND, None, None)?;
+                                    break;
+                                }
+                                Some(Err(e)) => {
+                                    log::warn!("failed to resolve pointer in {negotiated}: {e}");
+                                    let (status, headers, body) =
+                                        content_negotiation::not_acceptable(&extensions)?;
+                                    match method {
+                                        Method::HEAD => response(status, headers, None)?,
+                                        _ => response(status, headers, body)?,
+                                    }
+                                    break;
+                                }
+                            };
+                            let coding = encoding::negotiate(&headers, negotiated.media_type());
+                            let etag = encoding::etag(conditional::etag(&data), coding);
+
+                            if conditional::none_match(&headers, Some(&etag)) {
+                                if let Some(deadline) = deadline
+                                    && let Ok(Ok(())) =
+                                        tokio::time::timeout_at(deadline, changes.changed()).await
+                                {
+                                    continue;
+                                }
+
+                                let mut headers = HeaderMap::new();
+                                headers.append(header::ETAG, etag);
+                                vary(&path, &negotiated, &mut headers);
+                                if let Some(wait) = wait {
+                                    headers.append(
+                                        "preference-applied",
+                                        format!("wait={}", wait.as_secs()).parse()?,
+                                    );
+                                }
+                                response(StatusCode::NOT_MODIFIED, Some(headers), None)?;
+                                break;
                             }
-                            Method::GET => {
-                                response(StatusCode::OK, Some(headers), Some(Bytes::from(data)))?;
+
+                            let data = match coding {
+                                Some(coding) => encoding::encoded(
+                                    negotiated.as_ref(),
+                                    &data,
+                                    coding,
+                                    db.clone(),
+                                )?,
+                                None => data,
+                            };
+
+                            let mut headers = HeaderMap::new();
+                            headers.append(header::CONTENT_TYPE, negotiated.content_type_header());
+                            headers.append(header::CONTENT_LENGTH, data.len().into());
+                            headers.append(header::ETAG, etag);
+                            if let Some(coding) = coding {
+                                headers.append(header::CONTENT_ENCODING, coding.token().parse()?);
                             }
-                            _ => unreachable!(),
-                        };
-                    }
-                    Ok(None) => {
-                        log::error!(
-                            "negotiated path not found in database at key {negotiated} {}",
-                            format_args!("but extension was found in {:?}", extensions.path)
-                        );
-                        response(StatusCode::NOT_FOUND, None, None)?
-                    }
-                    Err(e) => {
-                        log::error!("error reading database at key {negotiated}: {e}");
-                        response(StatusCode::SERVICE_UNAVAILABLE, None, None)?;
-                    }
-                },
+                            vary(&path, &negotiated, &mut headers);
+                            let ext = negotiated.storage_extension();
+                            // a converted representation is listed among its alternates
+                            let this_ext = match negotiated.transcode() {
+                                Some(transcode) => transcode.to.name().into(),
+                                None => ext.clone(),
+                            };
+                            metadata::replay(
+                                extensions.get_attribute(&ext, metadata::ATTRIBUTE),
+                                &mut headers,
+                            );
+                            if let Some(language) = negotiated.language()
+                                && !headers.contains_key(header::CONTENT_LANGUAGE)
+                            {
+                                headers.append(header::CONTENT_LANGUAGE, language.parse()?);
+                            }
+                            for link in content_negotiation::alternates(&extensions, &this_ext)? {
+                                headers.append(header::LINK, link);
+                            }
+                            if let Some(expires) = extensions.get_expiry(&ext) {
+                                headers
+                                    .append(expiry::EXPIRES_HEADER, expiry::header_value(expires));
+                            }
+                            match method {
+                                Method::HEAD => {
+                                    response(StatusCode::OK, Some(headers), None)?;
+                                }
+                                Method::GET => {
+                                    response(
+                                        StatusCode::OK,
+                                        Some(headers),
+                                        Some(Bytes::from(data)),
+                                    )?;
+                                }
+                                _ => unreachable!(),
+                            };
+                        }
+                        Ok(None) => {
+                            log::error!(
+                                "negotiated path not found in database at key {negotiated} {}",
+                                format_args!("but extension was found in {:?}", extensions.path)
+                            );
+                            response(StatusCode::NOT_FOUND, None, None)?
+                        }
+                        Err(e) => {
+                            log::error!("error reading database at key {negotiated}: {e}");
+                            response(StatusCode::SERVICE_UNAVAILABLE, None, None)?;
+                        }
+                    },
+                }
+                break;
             }
         }
         (Method::PUT, path, headers) => {
             log::trace!("received PUT {path:?} with {headers:?}");
 
+            let expires = match expiry::requested(&headers) {
+                Ok(expires) => expires,
+                Err(e) => {
+                    log::warn!("rejected PUT {path:?}: {e}");
+                    response(StatusCode::BAD_REQUEST, None, None)?;
+                    return Ok(());
+                }
+            };
+
             match NegotiatedPath::for_write(&path, &headers)? {
                 None => response(StatusCode::UNSUPPORTED_MEDIA_TYPE, None, None)?,
-                Some(negotiated) => {
-                    let mut buf = vec![];
-                    while let Some(data) = body.data().await {
-                        let data = data?;
-                        let _ = body.flow_control().release_capacity(data.len());
-                        buf.put(data);
+                Some(mut negotiated) => {
+                    let buf = match received.take() {
+                        Some(buf) => buf,
+                        None => read_body(body).await?,
+                    };
+                    let buf = match encoding::decode_body(&headers, buf, config.max_decoded_size) {
+                        Ok(Ok(buf)) => buf,
+                        Ok(Err(status)) => {
+                            log::info!("refused PUT {path:?}: {status}");
+                            response(status, None, None)?;
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            log::warn!("rejected PUT {path:?}: {e}");
+                            response(StatusCode::BAD_REQUEST, None, None)?;
+                            return Ok(());
+                        }
+                    };
+                    if !headers.contains_key(header::CONTENT_TYPE) {
+                        if config.guess_types
+                            && path.extension().is_some()
+                            && let Err(e) = negotiated.guess_media_type()
+                        {
+                            log::debug!("media type guess failed for {negotiated}: {e}");
+                        }
+                        if config.sniff_types
+                            && *negotiated.media_type() == NegotiatedPath::GENERIC_MEDIA_TYPE
+                            && let Err(e) = negotiated.sniff_media_type(&buf)
+                        {
+                            log::debug!("media type sniffing failed for {negotiated}: {e}");
+                        }
+                    }
+
+                    // structured data is stored in the canonical format of its prefix
+                    let buf = match (
+                        config.transcoding.canonical_for(&path),
+                        Format::from_media_type(negotiated.media_type()),
+                    ) {
+                        (Some(canonical), Some(format)) => match format.convert(canonical, &buf) {
+                            Ok(converted) if format != canonical => {
+                                negotiated.set_format(canonical);
+                                converted
+                            }
+                            Ok(_) => buf,
+                            Err(e) => {
+                                log::warn!("rejected PUT {path:?}: {e}");
+                                response(StatusCode::BAD_REQUEST, None, None)?;
+                                return Ok(());
+                            }
+                        },
+                        _ => buf,
+                    };
+
+                    let violations = match config.schemas.violations(
+                        negotiated.as_ref(),
+                        negotiated.media_type(),
+                        &buf,
+                        db.clone(),
+                    ) {
+                        Ok(violations) => violations,
+                        Err(e) => {
+                            log::error!("failed to validate PUT {path:?}: {e}");
+                            response(StatusCode::INTERNAL_SERVER_ERROR, None, None)?;
+                            return Ok(());
+                        }
+                    };
+                    if !violations.is_empty() {
+                        log::info!(
+                            "rejected PUT {path:?}: {} schema violations",
+                            violations.len()
+                        );
+                        let (status, headers, body) = schemas::unprocessable(&violations)?;
+                        response(status, headers, body)?;
+                        return Ok(());
                     }
                     let value_size = buf.len();
+                    let etag = conditional::etag(&buf);
 
-                    let key_exists = db.get(&negotiated)?.is_some();
                     // request can change content-type of existing extension
                     let mut extensions = PathExtensions::get_for_path(&path, db.clone());
+                    let previous_type = extensions
+                        .get_media_type(&negotiated.storage_extension())?
+                        .map(|mt| mt.to_string());
+                    // an expired value is replaced as if it did not exist
+                    let previous = db.get(&negotiated)?.filter(|_| previous_type.is_some());
+                    let key_exists = previous.is_some();
+                    // a write-once key can not be replaced, nor expire
+                    if config.write_once.protects(negotiated.as_ref())
+                        && (key_exists || expires.is_some())
+                    {
+                        log::info!("refused PUT {negotiated}: write-once");
+                        let (status, headers) =
+                            methods::method_not_allowed(negotiated.as_ref(), key_exists, &config);
+                        response(status, Some(headers), None)?;
+                        return Ok(());
+                    }
 
-                    db.batch_update([
-                        (negotiated.as_ref(), Some(buf)),
-                        extensions.insert(&negotiated)?,
-                    ])?;
+                    let attributes = serde_json::Map::from_iter(
+                        expires
+                            .map(expiry::attribute)
+                            .into_iter()
+                            .chain(metadata::attribute(&headers)),
+                    );
+                    let (extensions_key, extensions_value) =
+                        extensions.insert_with(&negotiated, attributes)?;
+                    // variants of the previous value are replaced
+                    let mut updates = encoding::remove_variants(negotiated.as_ref(), db.clone())?;
+                    if config.precompress {
+                        updates.extend(encoding::store_variants(
+                            negotiated.as_ref(),
+                            &buf,
+                            negotiated.media_type(),
+                        )?);
+                    }
+                    updates.push((negotiated.as_ref().to_owned(), Some(buf)));
+                    updates.push((extensions_key.to_owned(), extensions_value));
+
+                    let mut version = None;
+                    if let Some(limit) = config.keep_versions {
+                        let mut history =
+                            VersionHistory::get_for_key(negotiated.as_ref(), db.clone());
+                        let previous = previous.map(|p| {
+                            (
+                                p,
+                                previous_type
+                                    .unwrap_or(NegotiatedPath::GENERIC_MEDIA_TYPE.to_string()),
+                            )
+                        });
+                        let media_type = negotiated.media_type().to_string();
+                        updates.extend(history.record_put(&media_type, previous, limit)?);
+                        version = history.latest().map(|v| v.id);
+                    }
+
+                    db.batch_update(updates)?;
 
                     let mut headers = HeaderMap::new();
                     headers.append(
                         header::CONTENT_LOCATION,
                         negotiated.content_location_header(),
                     );
+                    headers.append(header::ETAG, etag);
+                    if let Some(expires) = expires {
+                        headers.append(expiry::EXPIRES_HEADER, expiry::header_value(expires));
+                    }
+                    if let Some(version) = version {
+                        headers.append(versioning::VERSION_HEADER, version.into());
+                    }
 
                     if !key_exists {
                         log::info!("created {negotiated} ({value_size} bytes)");
@@ -143,24 +621,165 @@ async fn handle_request(
                 }
             }
         }
+        // a WebDAV client names a collection with a trailing slash
+        (Method::DELETE, path, headers)
+            if query.iter().any(|(k, _)| k == "recursive")
+                || (uri.path().len() > 1 && uri.path().ends_with('/')) =>
+        {
+            log::trace!("received DELETE {path:?} recursively with {headers:?}");
+            let resources = PathExtensions::resources(&path, db.clone())?;
+            if resources.iter().any(|r| config.write_once.protects(r)) {
+                log::info!("refused DELETE {path:?} recursively: write-once");
+                let (status, headers) = methods::method_not_allowed(&path, true, &config);
+                response(status, Some(headers), None)?;
+                return Ok(());
+            }
+            let mut updates = vec![];
+            let mut deleted = 0;
+            for resource in resources {
+                let mut extensions = PathExtensions::get_for_path(&resource, db.clone());
+                let all: Vec<String> = extensions.entries().map(|(ext, _)| ext.clone()).collect();
+                updates.extend(delete_representations(
+                    &resource,
+                    &mut extensions,
+                    &all,
+                    &config,
+                    db.clone(),
+                )?);
+                deleted += all.len();
+            }
+
+            let collections = webdav::collections(&path, db.clone())?;
+            for collection in collections.iter() {
+                updates.push((webdav::collection_key(collection), None));
+            }
+
+            if deleted == 0 && collections.is_empty() {
+                response(StatusCode::NOT_FOUND, None, None)?;
+            } else {
+                db.batch_update(updates)?;
+                log::info!("deleted {path:?} recursively ({deleted} representations)");
+                response(StatusCode::NO_CONTENT, None, None)?;
+            }
+        }
+        (Method::DELETE, path, headers) if query.iter().any(|(k, _)| k == "all") => {
+            log::trace!("received DELETE {path:?} with all representations with {headers:?}");
+            let mut extensions = PathExtensions::get_for_path(&path, db.clone());
+            let all: Vec<String> = extensions.entries().map(|(ext, _)| ext.clone()).collect();
+
+            if all.is_empty() {
+                response(StatusCode::NOT_FOUND, None, None)?;
+            } else if config.write_once.protects(&path) {
+                log::info!("refused DELETE {path:?}: write-once");
+                let (status, headers) = methods::method_not_allowed(&path, true, &config);
+                response(status, Some(headers), None)?;
+            } else {
+                let updates =
+                    delete_representations(&path, &mut extensions, &all, &config, db.clone())?;
+                db.batch_update(updates)?;
+                log::info!("deleted {path:?} ({} representations)", all.len());
+                response(StatusCode::NO_CONTENT, None, None)?;
+            }
+        }
         (Method::DELETE, path, headers) => {
             log::trace!("received DELETE {path:?} with {headers:?}");
             let mut extensions = PathExtensions::get_for_path(&path, db.clone());
 
-            match NegotiatedPath::for_read(&path, &extensions, &headers)? {
+            match NegotiatedPath::for_read(&path, &extensions, &headers, &config)? {
+                // a converted representation is not stored, so it can not be deleted
                 None => response(StatusCode::NOT_FOUND, None, None)?,
+                Some(negotiated) if negotiated.transcode().is_some() => {
+                    response(StatusCode::NOT_FOUND, None, None)?
+                }
+                Some(negotiated) if config.write_once.protects(negotiated.as_ref()) => {
+                    log::info!("refused DELETE {negotiated}: write-once");
+                    let (status, headers) = methods::method_not_allowed(&path, true, &config);
+                    response(status, Some(headers), None)?;
+                }
                 Some(negotiated) => {
                     let ext = negotiated.storage_extension().to_string();
                     let resource_desc = negotiated.to_string();
-                    let negotiated = negotiated.as_ref().to_owned();
-
-                    db.batch_update([(negotiated.as_path(), None), extensions.remove(&ext)?])?;
+                    let updates = delete_representations(
+                        &path,
+                        &mut extensions,
+                        &[ext],
+                        &config,
+                        db.clone(),
+                    )?;
+                    db.batch_update(updates)?;
 
                     log::info!("deleted {resource_desc}");
                     response(StatusCode::NO_CONTENT, None, None)?;
                 }
             }
         }
+        (Method::POST, path, headers)
+            if config.trash_retention.is_some() && query.iter().any(|(k, _)| k == "restore") =>
+        {
+            log::trace!("received POST {path:?} with {headers:?}");
+            let (status, headers) = trash::restore(&path, db)?;
+            response(status, headers, None)?;
+        }
+        (Method::OPTIONS, path, headers) => {
+            log::trace!("received OPTIONS {path:?} with {headers:?}");
+            let exists = methods::exists(&path, db)?;
+            response(
+                StatusCode::OK,
+                Some(methods::options(Some(&path), exists, &config)),
+                None,
+            )?;
+        }
+        (method, path, headers) if method.as_str() == "PROPFIND" => {
+            log::trace!("received PROPFIND {path:?} with {headers:?}");
+            let (status, headers, body) = match webdav::depth(&headers) {
+                Some(webdav::Depth::Finite(depth)) => webdav::propfind(&path, depth, db)?,
+                // listing a whole subtree is not supported, which is also what a request
+                // without a depth asks for
+                Some(webdav::Depth::Infinity) => webdav::finite_depth_required(),
+                None => (StatusCode::BAD_REQUEST, None, None),
+            };
+            response(status, headers, body)?;
+        }
+        (method, path, headers) if method.as_str() == "MKCOL" => {
+            log::trace!("received MKCOL {path:?} with {headers:?}");
+            if !body.is_end_stream() {
+                response(StatusCode::UNSUPPORTED_MEDIA_TYPE, None, None)?;
+                return Ok(());
+            }
+            match webdav::make_collection(&path, db)? {
+                StatusCode::CREATED => {
+                    log::info!("created collection {path:?}");
+                    response(StatusCode::CREATED, None, None)?;
+                }
+                // the collection or a resource exists already
+                StatusCode::METHOD_NOT_ALLOWED => {
+                    let (status, headers) = methods::method_not_allowed(&path, true, &config);
+                    response(status, Some(headers), None)?;
+                }
+                status => response(status, None, None)?,
+            }
+        }
+        (method, path, headers) if matches!(method.as_str(), "COPY" | "MOVE") => {
+            log::trace!("received {method} {path:?} with {headers:?}");
+            let Some(destination) = relocation::destination(&headers) else {
+                response(StatusCode::BAD_REQUEST, None, None)?;
+                return Ok(());
+            };
+            let remove = method.as_str() == "MOVE";
+            let overwrite = relocation::overwrite(&headers);
+            let (status, headers) =
+                relocation::relocate(&path, &destination, remove, overwrite, &config, db)?;
+            if status.is_success() {
+                log::info!("{method} {path:?} to {destination:?}");
+            }
+            response(status, headers, None)?;
+        }
+        (method, path, headers) if methods::is_known(&method) => {
+            log::info!("not allowed: {method:?} {path:?} with {headers:?}");
+            let exists = methods::exists(&path, db)?;
+            let (status, headers) = methods::method_not_allowed(&path, exists, &config);
+            response(status, Some(headers), None)?;
+        }
         (method, path, headers) => {
             log::error!("not implemented: {method:?} {path:?} with {headers:?}");
             response(StatusCode::NOT_IMPLEMENTED, None, None)?;
@@ -169,3 +788,96 @@ async fn handle_request(
 
     Ok(())
 }
+
+/// The status and headers of the response refusing a request for `method` on `path`, and on
+/// `destination` if it has one. A valid pre-signed URL grants access to its own path.
+fn refusal(
+    config: &Config,
+    presigned: &Presigned,
+    headers: &HeaderMap,
+    method: &Method,
+    path: &Path,
+    destination: Option<&Path>,
+) -> Option<(StatusCode, HeaderMap)> {
+    let source = match presigned {
+        Presigned::Valid => None,
+        _ => Some(path),
+    };
+    let paths: Vec<&Path> = [source, destination].into_iter().flatten().collect();
+    match paths.is_empty() {
+        true => None,
+        false => config.tokens.refusal(headers, method, &paths),
+    }
+}
+
+/// Returns the storage operations which delete the representations of the resource at `path`
+/// with `exts`, along with their entries and compressed variants. The deleted values are
+/// moved into the trash and recorded in the version history of each key, if enabled.
+fn delete_representations(
+    path: &Path,
+    extensions: &mut PathExtensions,
+    exts: &[String],
+    config: &Config,
+    db: Arc<impl StorageBackend>,
+) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+    let mut updates = vec![];
+    let mut trashed = vec![];
+    for ext in exts {
+        let key = extensions.key_of(ext);
+        let previous = db.get(&key)?;
+        updates.extend(encoding::remove_variants(&key, db.clone())?);
+        if config.trash_retention.is_some()
+            && let Some(ref value) = previous
+        {
+            let entry = extensions.get_entry(ext).cloned().unwrap_or_default();
+            trashed.push((ext.clone(), value.clone(), entry));
+        }
+        if let Some(limit) = config.keep_versions {
+            let media_type = extensions
+                .get_media_type(ext)?
+                .map(|mt| mt.to_string())
+                .unwrap_or(NegotiatedPath::GENERIC_MEDIA_TYPE.to_string());
+            let mut history = VersionHistory::get_for_key(&key, db.clone());
+            let previous = previous.map(|p| (p, media_type));
+            updates.extend(history.record_delete(previous, limit)?);
+        }
+    }
+    if !trashed.is_empty() {
+        updates.extend(trash::move_to_trash(path, trashed, db.clone())?);
+    }
+    updates.extend(extensions.remove_representations(exts)?);
+    Ok(updates)
+}
+
+/// Receive the whole body of a request.
+async fn read_body(body: &mut RecvStream) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    while let Some(data) = body.data().await {
+        let data = data?;
+        let _ = body.flow_control().release_capacity(data.len());
+        buf.put(data);
+    }
+    Ok(buf)
+}
+
+/// Name the request headers which were used to select the representation.
+fn vary(path: &Path, negotiated: &NegotiatedPath, headers: &mut HeaderMap) {
+    if path != negotiated.as_ref() {
+        headers.append(header::VARY, HeaderValue::from_static("accept"));
+        if negotiated.media_type().get_param(CHARSET).is_some() {
+            headers.append(header::VARY, HeaderValue::from_static("accept-charset"));
+        }
+        if negotiated.language().is_some() {
+            headers.append(header::VARY, HeaderValue::from_static("accept-language"));
+        }
+    }
+    if encoding::is_compressible(negotiated.media_type()) {
+        headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+fn query_pairs(uri: &Uri) -> Vec<(String, String)> {
+    form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect()
+}
@@ -4,17 +4,21 @@ use std::sync::mpsc::Sender;
 use leveldb::batch::{Batch, Writebatch};
 use leveldb::database::Database;
 use leveldb::database::serializable::Serializable;
+use leveldb::iterator::{Iterable, LevelDBIterator};
 use leveldb::kv::KV;
 use leveldb::options::{Options, ReadOptions, WriteOptions};
 
 use anyhow::{Context, Result};
+use tokio::sync::watch;
 
 use crate::storage::StorageBackend;
+use crate::storage::notify::KeyNotifier;
 
 pub struct DatabaseWrapper {
     db: Database<PathKey>,
     write_opts: WriteOptions,
     updates_tx: Sender<PathBuf>,
+    notifier: KeyNotifier,
 }
 
 impl DatabaseWrapper {
@@ -31,6 +35,7 @@ impl DatabaseWrapper {
             db,
             write_opts,
             updates_tx,
+            notifier: KeyNotifier::default(),
         })
     }
 }
@@ -51,6 +56,7 @@ impl StorageBackend for DatabaseWrapper {
             .put(self.write_opts, key, value)
             .with_context(|| format!("failed put {}", path.to_string_lossy()))?;
         self.updates_tx.send(path.to_owned())?;
+        self.notifier.notify(path);
 
         Ok(())
     }
@@ -62,6 +68,7 @@ impl StorageBackend for DatabaseWrapper {
             .delete(self.write_opts, key)
             .with_context(|| format!("failed delete {}", path.to_string_lossy()))?;
         self.updates_tx.send(path.to_owned())?;
+        self.notifier.notify(path);
         Ok(())
     }
 
@@ -72,6 +79,7 @@ impl StorageBackend for DatabaseWrapper {
         I: IntoIterator<Item = (K, Option<V>)>,
     {
         let mut batch = Writebatch::new();
+        let mut keys = vec![];
         for (k, v) in iter {
             let k = k.as_ref();
             match v {
@@ -79,11 +87,34 @@ impl StorageBackend for DatabaseWrapper {
                 None => batch.delete(PathKey(k.into())),
             }
             self.updates_tx.send(k.into())?;
+            keys.push(k.to_owned());
         }
         self.db.write(self.write_opts, &batch)?;
 
+        for k in keys {
+            self.notifier.notify(&k);
+        }
+
         Ok(())
     }
+
+    fn keys<P: AsRef<Path>>(&self, prefix: P) -> Result<Vec<PathBuf>> {
+        let prefix = PathKey(prefix.as_ref().into());
+        let prefix_bytes = prefix.as_u8();
+        let read_opts = ReadOptions::new();
+        let keys = self
+            .db
+            .keys_iter(read_opts)
+            .from(prefix)
+            .take_while(|k| k.as_u8().starts_with(&prefix_bytes))
+            .map(|k| k.0)
+            .collect();
+        Ok(keys)
+    }
+
+    fn subscribe<P: AsRef<Path>>(&self, path: P) -> watch::Receiver<()> {
+        self.notifier.subscribe(path.as_ref())
+    }
 }
 
 #[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
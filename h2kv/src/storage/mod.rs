@@ -1,9 +1,11 @@
 mod leveldb;
+mod notify;
 
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 
 use anyhow::Result;
+use tokio::sync::watch;
 
 pub struct StorageFactory;
 
@@ -32,4 +34,10 @@ pub trait StorageBackend: Send + Sync + 'static {
         K: AsRef<Path>,
         V: AsRef<[u8]>,
         I: IntoIterator<Item = (K, Option<V>)>;
+
+    /// Retrieve every key that starts with `prefix`, in byte order.
+    fn keys<P: AsRef<Path>>(&self, prefix: P) -> Result<Vec<PathBuf>>;
+
+    /// Receive a notification each time the value at `path` is stored or deleted.
+    fn subscribe<P: AsRef<Path>>(&self, path: P) -> watch::Receiver<()>;
 }
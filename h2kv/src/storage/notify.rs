@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use tokio::sync::watch;
+
+/// Wakes up subscribers when the value stored at a key is changed.
+/// Channels are created on demand and dropped once every subscriber is gone.
+#[derive(Default)]
+pub struct KeyNotifier {
+    senders: Mutex<HashMap<PathBuf, watch::Sender<()>>>,
+}
+
+impl KeyNotifier {
+    pub fn subscribe(&self, key: &Path) -> watch::Receiver<()> {
+        let mut senders = self.senders.lock().unwrap();
+        senders.retain(|_, tx| tx.receiver_count() > 0);
+        senders
+            .entry(key.to_owned())
+            .or_insert_with(|| watch::channel(()).0)
+            .subscribe()
+    }
+
+    pub fn notify(&self, key: &Path) {
+        let mut senders = self.senders.lock().unwrap();
+        if let Some(tx) = senders.get(key)
+            && tx.send(()).is_err()
+        {
+            // no subscribers left
+            senders.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify() {
+        let notifier = KeyNotifier::default();
+        let mut rx = notifier.subscribe(Path::new("/foo.txt"));
+        assert!(!rx.has_changed().unwrap());
+
+        notifier.notify(Path::new("/bar.txt"));
+        assert!(!rx.has_changed().unwrap());
+
+        notifier.notify(Path::new("/foo.txt"));
+        assert!(rx.has_changed().unwrap());
+        rx.mark_unchanged();
+
+        drop(rx);
+        notifier.notify(Path::new("/foo.txt"));
+        assert!(notifier.senders.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+use std::path::Path;
+
+use anyhow::{Result, anyhow, bail};
+use mediatype::MediaType;
+
+use crate::content_negotiation::Representation;
+
+/// Structured data formats which can be converted into each other,
+/// using the JSON data model as the common ground.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Cbor,
+    MessagePack,
+    Toml,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [
+        Format::Json,
+        Format::Yaml,
+        Format::Cbor,
+        Format::MessagePack,
+        Format::Toml,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Cbor => "cbor",
+            Format::MessagePack => "msgpack",
+            Format::Toml => "toml",
+        }
+    }
+
+    /// Also used as the file extension of the format.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "yml" => Some(Format::Yaml),
+            name => Self::ALL.into_iter().find(|f| f.name() == name),
+        }
+    }
+
+    pub fn media_type<'a>(&self) -> MediaType<'a> {
+        let media_type = match self {
+            Format::Json => "application/json",
+            Format::Yaml => "application/yaml",
+            Format::Cbor => "application/cbor",
+            Format::MessagePack => "application/msgpack",
+            Format::Toml => "application/toml",
+        };
+        MediaType::parse(media_type).unwrap()
+    }
+
+    pub fn from_media_type(media_type: &MediaType) -> Option<Self> {
+        match media_type.essence().to_string().as_str() {
+            "application/json" => Some(Format::Json),
+            "application/yaml" | "application/x-yaml" | "text/yaml" | "text/x-yaml" => {
+                Some(Format::Yaml)
+            }
+            "application/cbor" => Some(Format::Cbor),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            "application/toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Result<serde_json::Value> {
+        Ok(match self {
+            Format::Json => serde_json::from_slice(data)?,
+            Format::Yaml => serde_yaml::from_slice(data)?,
+            Format::Cbor => ciborium::from_reader(data)?,
+            Format::MessagePack => rmp_serde::from_slice(data)?,
+            Format::Toml => toml::from_str(std::str::from_utf8(data)?)?,
+        })
+    }
+
+    pub fn encode(&self, value: &serde_json::Value) -> Result<Vec<u8>> {
+        Ok(match self {
+            Format::Json => serde_json::to_vec(value)?,
+            Format::Yaml => serde_yaml::to_string(value)?.into_bytes(),
+            Format::Cbor => {
+                let mut encoded = vec![];
+                ciborium::into_writer(value, &mut encoded)?;
+                encoded
+            }
+            Format::MessagePack => rmp_serde::to_vec_named(value)?,
+            // TOML has no null and its documents are tables
+            Format::Toml => toml::to_string(value)?.into_bytes(),
+        })
+    }
+
+    pub fn convert(&self, to: Format, data: &[u8]) -> Result<Vec<u8>> {
+        to.encode(&self.decode(data)?)
+    }
+}
+
+/// A representation which is served by converting a stored representation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcode {
+    /// Extension of the stored representation.
+    pub extension: String,
+    pub from: Format,
+    pub to: Format,
+}
+
+impl Transcode {
+    pub fn apply(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.from.convert(self.to, data)
+    }
+}
+
+/// Canonical storage format by path prefix.
+#[derive(Debug, Clone, Default)]
+pub struct Transcoding {
+    /// Longest prefix first.
+    prefixes: Vec<(String, Format)>,
+}
+
+impl Transcoding {
+    pub const ENV_NAME: &str = "H2KV_TRANSCODE";
+    pub const ENV_DESCRIPTION: &str = r#"
+    Enables conversion between the structured data formats json, yaml, cbor, msgpack and toml.
+    Format:
+    Lines of a path prefix followed by the canonical format of objects under the prefix.
+    Objects written in one of the formats are stored in the canonical format,
+    and can be read in any of them. The line with the longest prefix applies.
+    Comments allowed between '#' and end of line.
+    Example: "/data/ json \n /config/ toml"
+    "#;
+
+    pub fn try_from_env() -> Result<Self> {
+        match std::env::var(Self::ENV_NAME) {
+            Ok(prefixes) => Self::try_from_str(&prefixes),
+            Err(std::env::VarError::NotPresent) => Ok(Self::default()),
+            Err(e) => Err(anyhow!(
+                "unparsed environment variable {}: {e}",
+                Self::ENV_NAME
+            )),
+        }
+    }
+
+    pub fn try_from_str(prefixes: &str) -> Result<Self> {
+        let mut parsed = vec![];
+        for line in prefixes.lines().flat_map(|line| line.split("\\n")) {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((prefix, name)) = line.split_once(char::is_whitespace) else {
+                bail!("expected a path prefix and a format: '{line}'");
+            };
+            let format =
+                Format::from_name(name.trim()).ok_or(anyhow!("unknown format '{name}'"))?;
+            parsed.push((prefix.to_string(), format));
+        }
+        parsed.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Ok(Self { prefixes: parsed })
+    }
+
+    /// The canonical format of objects at `path`, if transcoding is enabled for it.
+    pub fn canonical_for(&self, path: &Path) -> Option<Format> {
+        let path = path.to_string_lossy();
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map(|(_, format)| *format)
+    }
+}
+
+/// The representations in each format which can be converted from one of `available`,
+/// unless a representation in that format is available already.
+/// Translations are not converted.
+pub fn convertible<'a>(
+    available: &[Representation<'a>],
+    canonical: Format,
+) -> Vec<Representation<'a>> {
+    let Some((source, from)) = source(available, canonical) else {
+        return vec![];
+    };
+    Format::ALL
+        .into_iter()
+        .filter(|to| {
+            !available
+                .iter()
+                .any(|r| Format::from_media_type(&r.media_type) == Some(*to))
+        })
+        .map(|to| Representation {
+            extension: to.name(),
+            media_type: to.media_type(),
+            language: None,
+            transcode: Some(Transcode {
+                extension: source.to_string(),
+                from,
+                to,
+            }),
+        })
+        .collect()
+}
+
+/// The stored representation to convert from, preferably in the canonical format.
+fn source<'a>(available: &[Representation<'a>], canonical: Format) -> Option<(&'a str, Format)> {
+    let mut sources: Vec<(&'a str, Format)> = available
+        .iter()
+        .filter(|r| r.language.is_none() && r.transcode.is_none())
+        .filter_map(|r| Format::from_media_type(&r.media_type).map(|f| (r.extension, f)))
+        .collect();
+    sources.sort_by_key(|(_, format)| *format != canonical);
+    sources.into_iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let json = br#"{"name":"h2kv","tags":["http","kv"],"port":5928}"#;
+        let value = Format::Json.decode(json).unwrap();
+        for format in Format::ALL {
+            let encoded = Format::Json.convert(format, json).unwrap();
+            assert_eq!(format.decode(&encoded).unwrap(), value, "{format:?}");
+        }
+
+        assert!(Format::Json.convert(Format::Toml, b"[1, 2]").is_err());
+        assert!(Format::Yaml.decode(b"key: [unclosed").is_err());
+    }
+
+    #[test]
+    fn test_canonical_for() {
+        let transcoding = Transcoding::try_from_str("/data/ json \n /data/config/ toml").unwrap();
+        assert_eq!(
+            transcoding.canonical_for(Path::new("/data/config/app")),
+            Some(Format::Toml)
+        );
+        assert_eq!(
+            transcoding.canonical_for(Path::new("/data/app")),
+            Some(Format::Json)
+        );
+        assert_eq!(transcoding.canonical_for(Path::new("/app")), None);
+        assert!(Transcoding::try_from_str("/data/ xml").is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+
+use crate::content_negotiation::{NegotiatedPath, PathExtensions};
+use crate::storage::StorageBackend;
+use crate::util;
+
+/// Storage keys of users always start with "/", so keys in this namespace can not be addressed.
+/// Deleted representations and their extensions are kept here using the same layout
+/// as outside of the trash, with the deletion time recorded as an attribute of each extension.
+pub const NAMESPACE: &str = "trash";
+
+/// Name of the extension attribute holding the deletion time.
+const DELETED: &str = "deleted";
+
+/// Returns the storage operations which move representations of the resource at `path`
+/// into the trash, given the extension, current value and extension entry of each.
+pub fn move_to_trash(
+    path: &Path,
+    removed: Vec<(String, Vec<u8>, serde_json::Value)>,
+    db: Arc<impl StorageBackend>,
+) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+    let mut trashed = PathExtensions::get_in(NAMESPACE, path, db);
+    let deleted = util::micros(SystemTime::now());
+    let mut updates = vec![];
+    let mut extensions_update = None;
+    for (extension, value, entry) in removed {
+        let mut attributes = match entry {
+            serde_json::Value::Object(attributes) => attributes,
+            media_type => serde_json::Map::from_iter([("type".to_string(), media_type)]),
+        };
+        attributes.insert(DELETED.to_string(), deleted.into());
+        updates.push((trashed.key_of(&extension), Some(value)));
+        let (extensions_key, extensions_value) =
+            trashed.insert_entry(&extension, serde_json::Value::Object(attributes))?;
+        extensions_update = Some((extensions_key.to_owned(), extensions_value));
+    }
+    updates.extend(extensions_update);
+    Ok(updates)
+}
+
+/// Move a representation out of the trash, unless it has been replaced in the meantime.
+pub fn restore(
+    path: &Path,
+    db: Arc<impl StorageBackend>,
+) -> Result<(StatusCode, Option<HeaderMap>)> {
+    let key = match path.extension() {
+        Some(_) => path.to_owned(),
+        None => path.with_extension(NegotiatedPath::GENERIC_EXT),
+    };
+    let mut trashed = PathExtensions::get_in(NAMESPACE, &key, db.clone());
+    let extension = trashed.extension_of(&key).unwrap();
+
+    let mut extensions = PathExtensions::get_for_path(&key, db.clone());
+    if extensions.get_media_type(&extension)?.is_some() {
+        return Ok((StatusCode::CONFLICT, None));
+    }
+
+    // a representation which has expired, before or after it was deleted, is not restored
+    let Some(media_type) = trashed.get_media_type(&extension)?.map(|mt| mt.to_string()) else {
+        return Ok((StatusCode::NOT_FOUND, None));
+    };
+    let (mut entry, value) = match (trashed.get_entry(&extension), db.get(trash_key(&key))?) {
+        (Some(serde_json::Value::Object(entry)), Some(value)) => (entry.clone(), value),
+        _ => return Ok((StatusCode::NOT_FOUND, None)),
+    };
+    entry.remove(DELETED);
+
+    let (trashed_key, trashed_value) = trashed.remove(&extension)?;
+    let trashed_update = (trashed_key.to_owned(), trashed_value);
+    let (extensions_key, extensions_value) =
+        extensions.insert_entry(&extension, serde_json::Value::Object(entry))?;
+
+    db.batch_update([
+        (trash_key(&key), None),
+        trashed_update,
+        (key.clone(), Some(value)),
+        (extensions_key.to_owned(), extensions_value),
+    ])?;
+    log::info!("restored {key:?} {media_type}");
+
+    let mut headers = HeaderMap::new();
+    let location = match extension.as_str() {
+        NegotiatedPath::GENERIC_EXT => path.with_extension(""),
+        _ => key,
+    };
+    headers.append(
+        header::CONTENT_LOCATION,
+        HeaderValue::from_str(&location.to_string_lossy())?,
+    );
+    Ok((StatusCode::CREATED, Some(headers)))
+}
+
+/// A JSON listing of the trashed representations with a storage key starting with `prefix`.
+pub fn list(
+    prefix: &Path,
+    db: Arc<impl StorageBackend>,
+) -> Result<(StatusCode, Option<HeaderMap>, Option<Bytes>)> {
+    let mut listing = vec![];
+    for (key, media_type, deleted) in trashed_entries(prefix, db)? {
+        listing.push(serde_json::json!({
+            "key": key.to_string_lossy(),
+            "content-type": media_type,
+            "deleted": httpdate::fmt_http_date(deleted),
+        }));
+    }
+
+    let listing = serde_json::to_vec(&listing)?;
+    let mut headers = HeaderMap::new();
+    headers.append(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    headers.append(header::CONTENT_LENGTH, listing.len().into());
+    Ok((StatusCode::OK, Some(headers), Some(listing.into())))
+}
+
+/// Permanently delete every representation which has been in the trash longer than `retention`.
+pub fn purge(retention: Duration, db: Arc<impl StorageBackend>) -> Result<usize> {
+    let cutoff = SystemTime::now() - retention;
+    let mut purged = 0;
+
+    for extensions_key in db.keys(PathExtensions::key_prefix(NAMESPACE, Path::new("/")))? {
+        let Some(path) = PathExtensions::path_of(NAMESPACE, &extensions_key) else {
+            continue;
+        };
+
+        let mut trashed = PathExtensions::get_in(NAMESPACE, &path, db.clone());
+        let expired: Vec<String> = trashed
+            .entries()
+            .filter(|(ext, _)| deletion_time(&trashed, ext).is_none_or(|deleted| deleted < cutoff))
+            .map(|(ext, _)| ext.clone())
+            .collect();
+
+        let updates = trashed.remove_representations(&expired)?;
+
+        if !expired.is_empty() {
+            db.batch_update(updates)?;
+            purged += expired.len();
+        }
+    }
+
+    if purged > 0 {
+        log::info!("purged {purged} representations from the trash");
+    }
+    Ok(purged)
+}
+
+/// Every trashed representation with a storage key starting with `prefix`,
+/// identified by the storage key it had before it was deleted.
+fn trashed_entries(
+    prefix: &Path,
+    db: Arc<impl StorageBackend>,
+) -> Result<Vec<(PathBuf, String, SystemTime)>> {
+    let mut entries = vec![];
+    for extensions_key in db.keys(PathExtensions::key_prefix(NAMESPACE, prefix))? {
+        let Some(path) = PathExtensions::path_of(NAMESPACE, &extensions_key) else {
+            continue;
+        };
+
+        let trashed = PathExtensions::get_in(NAMESPACE, &path, db.clone());
+        for (ext, _) in trashed.entries() {
+            if let (Some(media_type), Some(deleted)) =
+                (trashed.get_media_type(ext)?, deletion_time(&trashed, ext))
+            {
+                let key = path.with_extension(ext);
+                entries.push((key, media_type.to_string(), deleted));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn deletion_time(trashed: &PathExtensions, extension: &str) -> Option<SystemTime> {
+    let deleted = trashed.get_attribute(extension, DELETED)?.as_u64()?;
+    Some(util::from_micros(deleted))
+}
+
+fn trash_key(key: &Path) -> PathBuf {
+    PathBuf::from(format!("{NAMESPACE}{}", key.to_string_lossy()))
+}
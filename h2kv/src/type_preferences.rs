@@ -0,0 +1,98 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+use headers_accept::Accept;
+use mediatype::MediaTypeBuf;
+
+/// Server-side quality of media types, applied on top of the *accept* header of a request.
+#[derive(Debug, Clone, Default)]
+pub struct TypePreferences {
+    /// Media ranges by path prefix, longest prefix first.
+    /// Ranges are ordered by specificity, as in a parsed *accept* header.
+    prefixes: Vec<(String, Vec<MediaTypeBuf>)>,
+}
+
+impl TypePreferences {
+    pub const ENV_NAME: &str = "H2KV_PREFER";
+    pub const ENV_DESCRIPTION: &str = r#"
+    Server-side quality of media types, used to choose between representations
+    which are equally acceptable to a client.
+    Format:
+    Lines of media ranges in the format of an Accept header, optionally preceded
+    by a path prefix. The line with the longest prefix of the request path applies.
+    Comments allowed between '#' and end of line.
+    Types which do not match any range have a quality of 1.
+    Example: "text/html, */*;q=0.5 \n /api/ application/json, */*;q=0.5"
+    "#;
+
+    pub fn try_from_env() -> Result<Self> {
+        match std::env::var(Self::ENV_NAME) {
+            Ok(preferences) => Self::try_from_str(&preferences),
+            Err(std::env::VarError::NotPresent) => Ok(Self::default()),
+            Err(e) => Err(anyhow!(
+                "unparsed environment variable {}: {e}",
+                Self::ENV_NAME
+            )),
+        }
+    }
+
+    pub fn try_from_str(preferences: &str) -> Result<Self> {
+        let mut prefixes = vec![];
+        for line in preferences.lines().flat_map(|line| line.split("\\n")) {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (prefix, ranges) = match line.starts_with('/') {
+                true => line.split_once(char::is_whitespace).unwrap_or((line, "")),
+                false => ("/", line),
+            };
+            let accept = Accept::from_str(ranges.trim())
+                .map_err(|e| anyhow!("invalid media ranges for {prefix:?} '{ranges}': {e}"))?;
+            prefixes.push((prefix.to_string(), accept.media_types().cloned().collect()));
+        }
+        prefixes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Ok(Self { prefixes })
+    }
+
+    /// The media ranges which apply to `path`, if any.
+    pub fn for_path(&self, path: &Path) -> &[MediaTypeBuf] {
+        let path = path.to_string_lossy();
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map(|(_, ranges)| ranges.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_path() {
+        let preferences = TypePreferences::try_from_str(
+            r#"
+            # browsers get html
+            text/html, */*;q=0.5
+            /api/ application/json  # except for the api
+            "#,
+        )
+        .unwrap();
+
+        let ranges = preferences.for_path(Path::new("/index"));
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].to_string(), "text/html");
+
+        let ranges = preferences.for_path(Path::new("/api/users"));
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].to_string(), "application/json");
+
+        let preferences = TypePreferences::try_from_str("/docs/ text/html").unwrap();
+        assert!(preferences.for_path(Path::new("/index")).is_empty());
+
+        assert!(TypePreferences::try_from_str("/api/ json").is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+
+use crate::storage::StorageBackend;
+use crate::util;
+
+/// Storage keys of users always start with "/", so keys in this namespace can not be addressed.
+pub const NAMESPACE: &str = "versions";
+
+pub const VERSION_HEADER: &str = "x-h2kv-version";
+
+/// Write history of a single storage key.
+/// Every write is recorded in an index under a version id (microseconds since the epoch).
+/// The value of the current version is the one stored at the key itself,
+/// previous values are copied into the versions namespace when they are replaced,
+/// at keys which can not be the index of another storage key.
+pub struct VersionHistory {
+    key: PathBuf,
+    index_key: PathBuf,
+    index: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Version {
+    pub id: u64,
+    pub media_type: Option<String>,
+    pub current: bool,
+}
+
+impl VersionHistory {
+    /// Instantiate from storage backend.
+    pub fn get_for_key(key: &Path, db: Arc<impl StorageBackend>) -> Self {
+        let index_key = index_key(key);
+        let index = db
+            .get(&index_key)
+            .ok()
+            .flatten()
+            .and_then(|s| serde_json::from_slice(s.as_slice()).ok())
+            .unwrap_or_default();
+        Self {
+            key: key.to_owned(),
+            index_key,
+            index,
+        }
+    }
+
+    /// Returns the storage operations which record a write of `media_type` to the key,
+    /// given the value being replaced and its media type.
+    pub fn record_put(
+        &mut self,
+        media_type: &str,
+        previous: Option<(Vec<u8>, String)>,
+        limit: usize,
+    ) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+        let entry = serde_json::json!({ "type": media_type });
+        self.record(entry, previous, limit)
+    }
+
+    /// Returns the storage operations which record a deletion of the key,
+    /// given the value being deleted and its media type.
+    pub fn record_delete(
+        &mut self,
+        previous: Option<(Vec<u8>, String)>,
+        limit: usize,
+    ) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+        let entry = serde_json::json!({ "deleted": true });
+        self.record(entry, previous, limit)
+    }
+
+    fn record(
+        &mut self,
+        entry: serde_json::Value,
+        previous: Option<(Vec<u8>, String)>,
+        limit: usize,
+    ) -> Result<Vec<(PathBuf, Option<Vec<u8>>)>> {
+        let mut ops = vec![];
+
+        if let Some((previous, previous_type)) = previous {
+            let previous_id = match self.latest() {
+                Some(v) if v.current => v.id,
+                // the value was written while versioning was disabled, at an unknown time
+                unrecorded => {
+                    let id = unrecorded.map(|v| v.id + 1).unwrap_or(0);
+                    let entry = serde_json::json!({ "type": previous_type });
+                    self.index.insert(id.to_string(), entry);
+                    id
+                }
+            };
+            ops.push((self.value_key(previous_id), Some(previous)));
+        }
+
+        let now = util::micros(SystemTime::now());
+        let id = match self.latest() {
+            Some(v) if v.id >= now => v.id + 1,
+            _ => now,
+        };
+        self.index.insert(id.to_string(), entry);
+
+        // the current version is not counted against the limit
+        let mut ids = self.ids();
+        ids.pop();
+        let excess = ids.len().saturating_sub(limit);
+        for id in ids.into_iter().take(excess) {
+            self.index.remove(&id.to_string());
+            ops.push((self.value_key(id), None));
+        }
+
+        let index_string = serde_json::to_string(&self.index)?;
+        ops.push((self.index_key.clone(), Some(index_string.into_bytes())));
+
+        Ok(ops)
+    }
+
+    /// The version with the given id.
+    pub fn find(&self, id: &str) -> Option<Version> {
+        let id = id.parse::<u64>().ok()?;
+        self.versions().into_iter().find(|v| v.id == id)
+    }
+
+    /// The version which was current at `time`.
+    pub fn at(&self, time: SystemTime) -> Option<Version> {
+        let time = util::micros(time);
+        self.versions().into_iter().rfind(|v| v.id <= time)
+    }
+
+    pub fn latest(&self) -> Option<Version> {
+        self.versions().pop()
+    }
+
+    /// Storage key of the value of `version`, unless it is a deletion.
+    pub fn value_key_of(&self, version: &Version) -> Option<PathBuf> {
+        match (&version.media_type, version.current) {
+            (None, _) => None,
+            (Some(_), true) => Some(self.key.clone()),
+            (Some(_), false) => Some(self.value_key(version.id)),
+        }
+    }
+
+    /// A JSON description of each version, latest first.
+    pub fn to_json(&self) -> serde_json::Value {
+        let versions = self
+            .versions()
+            .into_iter()
+            .rev()
+            .map(|v| {
+                serde_json::json!({
+                    "version": v.id.to_string(),
+                    "datetime": datetime(v.id).to_str().unwrap(),
+                    "content-type": v.media_type,
+                    "deleted": v.media_type.is_none(),
+                    "current": v.current,
+                })
+            })
+            .collect();
+        serde_json::Value::Array(versions)
+    }
+
+    fn versions(&self) -> Vec<Version> {
+        let mut versions: Vec<Version> = self
+            .ids()
+            .into_iter()
+            .map(|id| {
+                let entry = &self.index[&id.to_string()];
+                // deletions are recorded without a media type
+                let media_type = entry.get("type").and_then(|t| t.as_str()).map(String::from);
+                Version {
+                    id,
+                    media_type,
+                    current: false,
+                }
+            })
+            .collect();
+        if let Some(latest) = versions.last_mut() {
+            latest.current = latest.media_type.is_some();
+        }
+        versions
+    }
+
+    fn ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.index.keys().filter_map(|k| k.parse().ok()).collect();
+        ids.sort();
+        ids
+    }
+
+    fn value_key(&self, id: u64) -> PathBuf {
+        PathBuf::from(format!(
+            "{}{}{id}",
+            self.index_key.to_string_lossy(),
+            util::KEY_SEPARATOR
+        ))
+    }
+}
+
+fn index_key(key: &Path) -> PathBuf {
+    PathBuf::from(format!("{NAMESPACE}{}", key.to_string_lossy()))
+}
+
+/// Returns `true` if a read request refers to the version history of a representation.
+pub fn is_requested(query: &[(String, String)], headers: &HeaderMap) -> bool {
+    query.iter().any(|(k, _)| k == "version" || k == "versions")
+        || headers.contains_key("accept-datetime")
+}
+
+/// Serve a version of the representation stored at `key`, selected by the *version* query
+/// parameter or the *accept-datetime* header, or a listing of all versions.
+/// https://www.rfc-editor.org/rfc/rfc7089.html#section-2.1.1
+pub fn read(
+    key: &Path,
+    query: &[(String, String)],
+    headers: &HeaderMap,
+    db: Arc<impl StorageBackend>,
+) -> Result<(StatusCode, Option<HeaderMap>, Option<Bytes>)> {
+    let history = VersionHistory::get_for_key(key, db.clone());
+    let mut response_headers = HeaderMap::new();
+
+    if query.iter().any(|(k, _)| k == "versions") {
+        let listing = serde_json::to_vec(&history.to_json())?;
+        response_headers.append(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        response_headers.append(header::CONTENT_LENGTH, listing.len().into());
+        return Ok((StatusCode::OK, Some(response_headers), Some(listing.into())));
+    }
+
+    let version = match (
+        query.iter().find(|(k, _)| k == "version"),
+        headers.get("accept-datetime"),
+    ) {
+        (Some((_, id)), _) => history.find(id),
+        (None, Some(datetime)) => {
+            response_headers.append(header::VARY, HeaderValue::from_static("accept-datetime"));
+            let datetime = httpdate::parse_http_date(datetime.to_str()?)
+                .map_err(|e| anyhow!("invalid accept-datetime: {e}"));
+            match datetime {
+                Ok(datetime) => history.at(datetime),
+                Err(_) => return Ok((StatusCode::BAD_REQUEST, None, None)),
+            }
+        }
+        (None, None) => history.latest(),
+    };
+
+    let (version, value_key) = match version {
+        Some(version) => match history.value_key_of(&version) {
+            Some(value_key) => (version, value_key),
+            None => return Ok((StatusCode::NOT_FOUND, Some(response_headers), None)),
+        },
+        None => return Ok((StatusCode::NOT_FOUND, Some(response_headers), None)),
+    };
+
+    match db.get(&value_key)? {
+        None => {
+            log::error!(
+                "version {} of {key:?} not found at {value_key:?}",
+                version.id
+            );
+            Ok((StatusCode::NOT_FOUND, Some(response_headers), None))
+        }
+        Some(data) => {
+            let media_type = version.media_type.unwrap();
+            response_headers.append(header::CONTENT_TYPE, media_type.parse()?);
+            response_headers.append(header::CONTENT_LENGTH, data.len().into());
+            response_headers.append(header::ETAG, crate::conditional::etag(&data));
+            response_headers.append(VERSION_HEADER, version.id.into());
+            if version.id > 0 {
+                response_headers.append("memento-datetime", datetime(version.id));
+            }
+            Ok((StatusCode::OK, Some(response_headers), Some(data.into())))
+        }
+    }
+}
+
+fn datetime(id: u64) -> HeaderValue {
+    let time = util::from_micros(id);
+    HeaderValue::from_str(&httpdate::fmt_http_date(time)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> VersionHistory {
+        VersionHistory {
+            key: PathBuf::from("/foo.txt"),
+            index_key: PathBuf::from("versions/foo.txt"),
+            index: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let mut history = history();
+
+        let ops = history.record_put("text/plain", None, 2).unwrap();
+        assert_eq!(ops.len(), 1);
+        let v1 = history.latest().unwrap();
+        assert!(v1.current);
+
+        let previous = (b"v1".to_vec(), "text/plain".to_string());
+        let ops = history.record_put("text/plain", Some(previous), 2).unwrap();
+        let v1_key = PathBuf::from(format!("versions/foo.txt\0{}", v1.id));
+        // the index of a nested key is elsewhere
+        assert_ne!(index_key(Path::new(&format!("/foo.txt/{}", v1.id))), v1_key);
+        assert_eq!(ops[0], (v1_key.clone(), Some(b"v1".to_vec())));
+        let v1 = history.find(&v1.id.to_string()).unwrap();
+        assert_eq!(history.value_key_of(&v1), Some(v1_key));
+        let v2 = history.latest().unwrap();
+        assert!(v2.id > v1.id);
+        assert_eq!(history.value_key_of(&v2), Some(PathBuf::from("/foo.txt")));
+
+        let previous = (b"v2".to_vec(), "text/plain".to_string());
+        history.record_delete(Some(previous), 2).unwrap();
+        let deleted = history.latest().unwrap();
+        assert!(!deleted.current);
+        assert_eq!(history.value_key_of(&deleted), None);
+
+        // v1 is pruned
+        let ops = history.record_put("text/plain", None, 2).unwrap();
+        assert!(ops.contains(&(PathBuf::from(format!("versions/foo.txt\0{}", v1.id)), None)));
+        assert_eq!(history.find(&v1.id.to_string()), None);
+        assert_eq!(history.versions().len(), 3);
+    }
+
+    #[test]
+    fn test_at() {
+        let mut history = history();
+        history.record_put("text/plain", None, 2).unwrap();
+        let v1 = history.latest().unwrap();
+
+        assert_eq!(history.at(std::time::UNIX_EPOCH), None);
+        assert_eq!(history.at(SystemTime::now()), Some(v1));
+    }
+}
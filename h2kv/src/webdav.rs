@@ -0,0 +1,312 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, StatusCode, header};
+
+use crate::conditional;
+use crate::content_negotiation::PathExtensions;
+use crate::storage::StorageBackend;
+use crate::util;
+
+/// Storage keys of users always start with "/", so keys in this namespace can not be addressed.
+/// Collections are implied by the keys below them, so only empty ones created with MKCOL
+/// are recorded here, using the same layout as the user namespace.
+pub const NAMESPACE: &str = "collections";
+
+/// Compliance class advertised to WebDAV clients.
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-10.1
+pub const DAV_HEADER: &str = "dav";
+
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-10.2
+pub const DEPTH_HEADER: &str = "depth";
+
+/// The *depth* of a PROPFIND request.
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-10.2
+#[derive(Debug, PartialEq)]
+pub enum Depth {
+    Finite(u8),
+    Infinity,
+}
+
+/// The *depth* of a PROPFIND request, which is infinity if the header is missing,
+/// or None if the header is not a valid depth.
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-9.1
+pub fn depth(headers: &HeaderMap) -> Option<Depth> {
+    let Some(value) = headers.get(DEPTH_HEADER) else {
+        return Some(Depth::Infinity);
+    };
+    match value.to_str().ok()? {
+        "0" => Some(Depth::Finite(0)),
+        "1" => Some(Depth::Finite(1)),
+        "infinity" => Some(Depth::Infinity),
+        _ => None,
+    }
+}
+
+/// Refusal of a PROPFIND request for a whole subtree, which is not supported.
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-9.1
+pub fn finite_depth_required() -> (StatusCode, Option<HeaderMap>, Option<Bytes>) {
+    let mut body = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    body.push_str(r#"<D:error xmlns:D="DAV:"><D:propfind-finite-depth/></D:error>"#);
+    (
+        StatusCode::FORBIDDEN,
+        Some(xml_headers(&body)),
+        Some(body.into()),
+    )
+}
+
+/// Paths of every empty collection recorded at or below `prefix`.
+pub fn collections(prefix: &Path, db: Arc<impl StorageBackend>) -> Result<Vec<PathBuf>> {
+    let keys = db.keys(collection_key(prefix))?;
+    Ok(keys
+        .iter()
+        .filter_map(|key| key.to_str()?.strip_prefix(NAMESPACE).map(PathBuf::from))
+        .filter(|path| path.starts_with(prefix))
+        .collect())
+}
+
+/// Storage key recording the empty collection at `path`.
+pub fn collection_key(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{NAMESPACE}{}", trimmed(path).to_string_lossy()))
+}
+
+/// Whether `path` names the root, an empty collection, or a prefix of stored resources.
+pub fn is_collection(path: &Path, db: Arc<impl StorageBackend>) -> Result<bool> {
+    let path = trimmed(path);
+    if path == Path::new("/") || db.get(collection_key(&path))?.is_some() {
+        return Ok(true);
+    }
+    let below = format!("{}/", path.to_string_lossy());
+    Ok(!db
+        .keys(PathExtensions::key_prefix("", Path::new(&below)))?
+        .is_empty())
+}
+
+/// Record an empty collection at `path`.
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-9.3
+pub fn make_collection(path: &Path, db: Arc<impl StorageBackend>) -> Result<StatusCode> {
+    let path = trimmed(path);
+    if is_collection(&path, db.clone())? || db.get(&path)?.is_some() {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let parent_exists = match path.parent() {
+        Some(parent) => is_collection(parent, db.clone())?,
+        None => false,
+    };
+    if !parent_exists {
+        return Ok(StatusCode::CONFLICT);
+    }
+    db.put(collection_key(&path), b"")?;
+    Ok(StatusCode::CREATED)
+}
+
+/// A multistatus response with the properties of the resource at `path`,
+/// and those of the members of a collection if `depth` is 1.
+/// https://www.rfc-editor.org/rfc/rfc4918.html#section-9.1
+pub fn propfind(
+    path: &Path,
+    depth: u8,
+    db: Arc<impl StorageBackend>,
+) -> Result<(StatusCode, Option<HeaderMap>, Option<Bytes>)> {
+    let mut responses = vec![];
+
+    let extensions = PathExtensions::get_for_path(path, db.clone());
+    let file = match path.to_string_lossy().ends_with('/') {
+        true => None,
+        false => extensions
+            .representations()?
+            .into_iter()
+            .find(|r| extensions.location_of(r.extension) == path)
+            .map(|r| r.extension.to_string()),
+    };
+    if let Some(ext) = file {
+        responses.push(file_response(&extensions, &ext, db.clone())?);
+    } else if is_collection(path, db.clone())? {
+        let path = trimmed(path);
+        responses.push(collection_response(&path));
+        if depth > 0 {
+            responses.extend(member_responses(&path, db.clone())?);
+        }
+    } else {
+        return Ok((StatusCode::NOT_FOUND, None, None));
+    }
+
+    let mut body = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    body.push_str(r#"<D:multistatus xmlns:D="DAV:">"#);
+    body.extend(responses);
+    body.push_str("</D:multistatus>");
+
+    Ok((
+        StatusCode::MULTI_STATUS,
+        Some(xml_headers(&body)),
+        Some(body.into()),
+    ))
+}
+
+/// Headers of an XML `body`.
+fn xml_headers(body: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.append(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/xml; charset=utf-8"),
+    );
+    headers.append(header::CONTENT_LENGTH, body.len().into());
+    headers
+}
+
+/// Responses for the files and collections directly below the collection at `path`.
+fn member_responses(path: &Path, db: Arc<impl StorageBackend>) -> Result<Vec<String>> {
+    let mut files = vec![];
+    let mut collections = BTreeSet::new();
+    let below = match path == Path::new("/") {
+        true => PathBuf::from("/"),
+        false => PathBuf::from(format!("{}/", path.to_string_lossy())),
+    };
+
+    let resources = PathExtensions::resources(&below, db.clone())?;
+    for member in resources
+        .iter()
+        .chain(collections_below(&below, db.clone())?.iter())
+    {
+        let Ok(relative) = member.strip_prefix(path) else {
+            continue;
+        };
+        let mut components = relative.components();
+        match (components.next(), components.next()) {
+            (Some(name), Some(_)) => {
+                collections.insert(path.join(name));
+            }
+            (Some(_), None) if resources.contains(member) => {
+                let extensions = PathExtensions::get_for_path(member, db.clone());
+                for representation in extensions.representations()? {
+                    files.push(file_response(
+                        &extensions,
+                        representation.extension,
+                        db.clone(),
+                    )?);
+                }
+            }
+            (Some(name), None) => {
+                collections.insert(path.join(name));
+            }
+            (None, _) => (),
+        }
+    }
+
+    let mut responses: Vec<String> = collections.iter().map(|c| collection_response(c)).collect();
+    responses.extend(files);
+    Ok(responses)
+}
+
+fn collections_below(below: &Path, db: Arc<impl StorageBackend>) -> Result<Vec<PathBuf>> {
+    Ok(collections(below, db)?
+        .into_iter()
+        .filter(|c| c != &trimmed(below))
+        .collect())
+}
+
+fn file_response(
+    extensions: &PathExtensions,
+    extension: &str,
+    db: Arc<impl StorageBackend>,
+) -> Result<String> {
+    let href = extensions.location_of(extension);
+    let value = db.get(extensions.key_of(extension))?.unwrap_or_default();
+    let media_type = extensions
+        .get_media_type(extension)?
+        .map(|mt| mt.to_string())
+        .unwrap_or_default();
+    let etag = conditional::etag(&value);
+
+    let mut props = String::from("<D:resourcetype/>");
+    write!(
+        props,
+        "<D:displayname>{}</D:displayname>",
+        display_name(&href)
+    )?;
+    write!(
+        props,
+        "<D:getcontenttype>{}</D:getcontenttype>",
+        util::xml_escape(&media_type)
+    )?;
+    write!(
+        props,
+        "<D:getcontentlength>{}</D:getcontentlength>",
+        value.len()
+    )?;
+    write!(
+        props,
+        "<D:getetag>{}</D:getetag>",
+        util::xml_escape(etag.to_str()?)
+    )?;
+    Ok(response(&href.to_string_lossy(), &props))
+}
+
+fn collection_response(path: &Path) -> String {
+    let href = match path == Path::new("/") {
+        true => "/".to_string(),
+        false => format!("{}/", path.to_string_lossy()),
+    };
+    let props = format!(
+        "<D:resourcetype><D:collection/></D:resourcetype><D:displayname>{}</D:displayname>",
+        display_name(path)
+    );
+    response(&href, &props)
+}
+
+fn response(href: &str, props: &str) -> String {
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>{props}</D:prop>\
+        <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>",
+        util::xml_escape(href)
+    )
+}
+
+fn display_name(path: &Path) -> String {
+    util::xml_escape(&path.file_name().unwrap_or_default().to_string_lossy())
+}
+
+/// `path` without a trailing slash, unless it is the root.
+pub fn trimmed(path: &Path) -> PathBuf {
+    let path = path.to_string_lossy();
+    match path.trim_end_matches('/') {
+        "" => PathBuf::from("/"),
+        trimmed => PathBuf::from(trimmed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth() {
+        let headers = |value: &'static str| {
+            HeaderMap::from_iter([(
+                DEPTH_HEADER.try_into().unwrap(),
+                HeaderValue::from_static(value),
+            )])
+        };
+        assert_eq!(depth(&headers("0")), Some(Depth::Finite(0)));
+        assert_eq!(depth(&headers("1")), Some(Depth::Finite(1)));
+        assert_eq!(depth(&headers("infinity")), Some(Depth::Infinity));
+        assert_eq!(depth(&HeaderMap::new()), Some(Depth::Infinity));
+        assert_eq!(depth(&headers("2")), None);
+    }
+
+    #[test]
+    fn test_collection_response() {
+        assert_eq!(
+            collection_response(Path::new("/a&b")),
+            "<D:response><D:href>/a&amp;b/</D:href><D:propstat><D:prop>\
+            <D:resourcetype><D:collection/></D:resourcetype><D:displayname>a&amp;b</D:displayname>\
+            </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>"
+        );
+        assert_eq!(trimmed(Path::new("/docs/")), PathBuf::from("/docs"));
+        assert_eq!(trimmed(Path::new("/")), PathBuf::from("/"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::Path;
+
+use anyhow::{Result, anyhow, bail};
+use http::Method;
+
+/// Path prefixes of write-once keys, which can be created but never overwritten nor deleted.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOnce {
+    prefixes: Vec<String>,
+}
+
+impl WriteOnce {
+    pub const ENV_NAME: &str = "H2KV_WRITE_ONCE";
+    pub const ENV_DESCRIPTION: &str = r#"
+    Path prefixes of write-once keys, which can be created once but never overwritten,
+    deleted, moved, or given an expiry. Such requests are rejected with 405 Method Not Allowed.
+    Format:
+    String of path prefixes separated by spaces or newline characters.
+    Comments allowed between '#' and end of line.
+    Example: "/archive/ /releases/"
+    "#;
+
+    pub fn try_from_env() -> Result<Self> {
+        match std::env::var(Self::ENV_NAME) {
+            Ok(prefixes) => Self::try_from_str(&prefixes),
+            Err(std::env::VarError::NotPresent) => Ok(Self::default()),
+            Err(e) => Err(anyhow!(
+                "unparsed environment variable {}: {e}",
+                Self::ENV_NAME
+            )),
+        }
+    }
+
+    pub fn try_from_str(prefixes: &str) -> Result<Self> {
+        let prefixes: Vec<String> = prefixes
+            .lines()
+            .flat_map(|line| line.split("\\n"))
+            .flat_map(|line| line.split('#').next().unwrap().split_whitespace())
+            .map(|prefix| prefix.to_string())
+            .collect();
+        if let Some(prefix) = prefixes.iter().find(|prefix| !prefix.starts_with('/')) {
+            bail!("write-once prefix should be an absolute path: {prefix:?}");
+        }
+        Ok(Self { prefixes })
+    }
+
+    /// Whether the key or resource at `path` can only be written once.
+    pub fn protects(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy();
+        self.prefixes
+            .iter()
+            .any(|prefix| path.starts_with(prefix.as_str()))
+    }
+}
+
+/// Whether a request with `method` can change the stored data.
+pub fn is_write(method: &Method) -> bool {
+    matches!(
+        method.as_str(),
+        "PUT" | "DELETE" | "PATCH" | "POST" | "COPY" | "MOVE" | "MKCOL"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protects() {
+        let write_once = WriteOnce::try_from_str("/archive/ # comment\\n/releases/v1").unwrap();
+        assert!(write_once.protects(Path::new("/archive/2024/a.txt")));
+        assert!(write_once.protects(Path::new("/releases/v1.2.tar.gz")));
+        assert!(!write_once.protects(Path::new("/archive")));
+        assert!(!write_once.protects(Path::new("/drafts/a.txt")));
+        assert!(!WriteOnce::default().protects(Path::new("/archive/a.txt")));
+        assert!(WriteOnce::try_from_str("archive/").is_err());
+    }
+}
@@ -0,0 +1,10 @@
+#!/bin/sh
+# hurl always requests a path, so the server-wide `OPTIONS *` is sent with curl
+# https://www.rfc-editor.org/rfc/rfc9110.html#section-9.3.7
+set -e
+headers=$(curl --http2 --insecure --silent --show-error \
+    --request OPTIONS --request-target '*' \
+    --dump-header - --output /dev/null "https://localhost:$1" | tr -d '\r')
+echo "$headers" | grep --quiet --line-regexp 'HTTP/2 200'
+echo "$headers" | grep --quiet --line-regexp \
+    'allow: GET, HEAD, OPTIONS, PROPFIND, PUT, DELETE, COPY, MOVE, MKCOL, POST'
@@ -60,12 +60,19 @@ fn test_integration() -> Result<(), DynError> {
 
     let server = ServerProcess::try_start(9080, sync_dir)?;
     let _proxy = TlsProxy::try_start(8443, 9080)?;
+    let _auth_server = ServerProcess::try_start_with_tokens(9081, "tests/auth/tokens.txt")?;
+    let _auth_proxy = TlsProxy::try_start(8444, 9081)?;
 
     let result = nix_shell(
         "hurl \
             --http2 --insecure --variable PORT=8443 \
             --test --jobs 1 \
-            tests/*.hurl",
+            tests/*.hurl \
+        && sh tests/19_options.sh 8443 \
+        && hurl \
+            --http2 --insecure --variable PORT=8444 \
+            --test --jobs 1 \
+            tests/auth/*.hurl",
     )?
     .wait();
 
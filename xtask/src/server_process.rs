@@ -16,6 +16,8 @@ impl ServerProcess {
         let temp_dir = read!("mktemp", "--directory")?;
         let temp_dir = temp_dir.trim();
         let pid_file = format!("{temp_dir}/h2kv.pid");
+        let signing_key_file = format!("{temp_dir}/signing.key");
+        fs::write(&signing_key_file, "h2kv test signing key")?;
         let bin_path = Self::bin_path()?;
 
         Cmd::new(bin_path)
@@ -27,6 +29,20 @@ impl ServerProcess {
                 "--sync-dir",
                 sync_dir,
                 "--sync-write",
+                "--s3-endpoint",
+                "/s3",
+                "--signing-key-file",
+                &signing_key_file,
+                "--cors-origins",
+                "https://app.example.com",
+                "--keep-versions",
+                "2",
+                "--trash-retention",
+                "3600",
+                "--precompress",
+                "--max-decoded-size",
+                "1048576",
+                "--sniff-types",
                 "--daemon",
                 "--pidfile",
                 &pid_file,
@@ -34,9 +50,48 @@ impl ServerProcess {
                 "/dev/fd/2",
             ])
             .env("H2KV_IGNORE", "**/* !/sync_file.* !/new/*")
+            .env("H2KV_PREFER", "/preferred/ text/html, */*;q=0.5")
+            .env("H2KV_TRANSCODE", "/data/ json")
+            .env("H2KV_SCHEMAS", "/validated/ /schemas/validated.json")
+            .env("H2KV_WRITE_ONCE", "/write-once/")
             .log_err(Some(log::Level::Debug))
             .spawn()?
             .wait()?;
+        Self::try_wait(temp_dir, &pid_file)
+    }
+
+    /// A server which requires the bearer tokens defined in `tokens_file`.
+    pub fn try_start_with_tokens(port: u32, tokens_file: &str) -> Result<Self, DynError> {
+        run!("test", "-f", tokens_file)?;
+
+        let temp_dir = read!("mktemp", "--directory")?;
+        let temp_dir = temp_dir.trim();
+        let pid_file = format!("{temp_dir}/h2kv.pid");
+        let bin_path = Self::bin_path()?;
+
+        Cmd::new(bin_path)
+            .args(&[
+                "--port",
+                port.to_string().as_str(),
+                "--storage-dir",
+                temp_dir,
+                "--tokens-file",
+                tokens_file,
+                "--s3-endpoint",
+                "/s3",
+                "--daemon",
+                "--pidfile",
+                &pid_file,
+                "--log-filename",
+                "/dev/fd/2",
+            ])
+            .log_err(Some(log::Level::Debug))
+            .spawn()?
+            .wait()?;
+        Self::try_wait(temp_dir, &pid_file)
+    }
+
+    fn try_wait(temp_dir: &str, pid_file: &str) -> Result<Self, DynError> {
         let server_pid = read!("cat", pid_file)?.trim().to_string();
 
         // wait for server to start